@@ -0,0 +1,292 @@
+use crate::cli::{print_error, print_info, print_success};
+use crate::db;
+use crate::pkg::{package_path, read_meta, META_PATH};
+use crate::repo::Meta;
+use crate::version;
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tar::{Archive, Builder, EntryType, Header};
+
+/// Catch what the `meta.toml` schema alone does not: fields a repository index would choke on.
+fn validate_meta(meta: &Meta) -> Result<()> {
+    let valid_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c))
+    };
+
+    if !valid_name(&meta.name) {
+        bail!("Invalid package name '{}'", meta.name);
+    }
+    if meta.version.is_empty() || meta.version.contains(char::is_whitespace) {
+        bail!("Invalid version '{}'", meta.version);
+    }
+    for (field, value) in [("arch", &meta.arch), ("os", &meta.os)] {
+        if value.is_empty() {
+            bail!("'{}' must not be empty", field);
+        }
+    }
+    if let Some(dep) = meta.deps.iter().find(|dep| !valid_name(dep)) {
+        bail!("Invalid dependency '{}'", dep);
+    }
+    if let Some(path) = meta.config.iter().find(|path| !path.starts_with('/')) {
+        bail!("Config path '{}' must be absolute", path);
+    }
+    Ok(())
+}
+
+/// Every path under `dir` but its meta.toml, relative to `dir`, sorted and parents first
+/// so the archive does not depend on directory listing order.
+fn payload(dir: &Path, meta_file: &Path) -> Result<Vec<PathBuf>> {
+    fn walk(dir: &Path, base: &Path, meta_file: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+        let mut children = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read {}", dir.display()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        children.sort();
+
+        for path in children {
+            let relative = path.strip_prefix(base)?.to_path_buf();
+            if relative == meta_file {
+                continue;
+            }
+            let is_dir = fs::symlink_metadata(&path)?.is_dir();
+            out.push(relative);
+            if is_dir {
+                walk(&path, base, meta_file, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut paths = Vec::new();
+    walk(dir, dir, meta_file, &mut paths)?;
+    Ok(paths)
+}
+
+/// The mtime stamped on every entry: `SOURCE_DATE_EPOCH` if set, otherwise 0.
+fn build_timestamp() -> Result<u64> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid SOURCE_DATE_EPOCH '{}'", value)),
+        Err(_) => Ok(0),
+    }
+}
+
+/// A header with everything that differs between build machines normalized away:
+/// owned by root:root, a fixed mtime, and only the permission bits of the mode.
+fn normalized_header(entry_type: EntryType, mode: u32, size: u64, mtime: u64) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(mode & 0o7777);
+    header.set_size(size);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(mtime);
+    header
+}
+
+/// Package `dir` (its `meta.toml` plus everything else in it, laid out as installed)
+/// into a reproducible `.pkg` and print the checksum and size for the repository index.
+pub fn build(dir: &Path, output: Option<&Path>) -> Result<()> {
+    let meta_file = Path::new(META_PATH.trim_start_matches('/'));
+    let meta_path = dir.join(meta_file);
+    let meta_content = fs::read_to_string(&meta_path)
+        .with_context(|| format!("Failed to read {}", meta_path.display()))?;
+    let meta: Meta = toml::from_str(&meta_content)
+        .with_context(|| format!("Invalid {}", meta_path.display()))?;
+    validate_meta(&meta)?;
+
+    let paths = payload(dir, meta_file)?;
+    if paths.is_empty() {
+        bail!("{} has nothing to package besides meta.toml", dir.display());
+    }
+
+    let mtime = build_timestamp()?;
+    let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::best()));
+
+    // The metadata goes first, at its reserved place in the archive root.
+    let mut header = normalized_header(EntryType::Regular, 0o644, meta_content.len() as u64, mtime);
+    builder.append_data(&mut header, meta_file, meta_content.as_bytes())?;
+
+    for relative in &paths {
+        let path = dir.join(relative);
+        let metadata = fs::symlink_metadata(&path)?;
+        let mode = metadata.permissions().mode();
+        let file_type = metadata.file_type();
+
+        if file_type.is_symlink() {
+            let mut header = normalized_header(EntryType::Symlink, 0o777, 0, mtime);
+            builder.append_link(&mut header, relative, fs::read_link(&path)?)?;
+        } else if file_type.is_dir() {
+            let mut header = normalized_header(EntryType::Directory, mode, 0, mtime);
+            builder.append_data(&mut header, relative, io::empty())?;
+        } else if file_type.is_file() {
+            let mut header = normalized_header(EntryType::Regular, mode, metadata.len(), mtime);
+            let file = File::open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            builder.append_data(&mut header, relative, file)?;
+        } else {
+            bail!("{} is not a regular file, directory or symlink", path.display());
+        }
+    }
+
+    let data = builder
+        .into_inner()
+        .and_then(|gz| gz.finish())
+        .context("Failed to compress package")?;
+
+    let output = output
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(format!("{}-{}.pkg", meta.name, meta.version)));
+    db::write_atomic(&output, &data)?;
+
+    print_success(&format!("Built {} {}: {}", meta.name, meta.version, output.display()));
+    println!("sha256: {:x}", Sha256::digest(&data));
+    println!("size:   {}", data.len());
+    Ok(())
+}
+
+/// Index entry for one archive, in the shape `repo::fetch_repository` reads.
+fn index_entry(meta: &Meta, archive: &Path) -> Result<Value> {
+    let file_name = archive.file_name().unwrap_or_default().to_string_lossy();
+    let mut entry = json!({
+        "version": meta.version,
+        "description": meta.description.as_deref().unwrap_or("No description"),
+        "url": file_name,
+        "os": meta.os,
+        "arch": meta.arch,
+        "deps": meta.deps,
+        "author": meta.author,
+        "license": meta.license,
+        "size": fs::metadata(archive)?.len(),
+        "sha256": db::sha256_file(archive)?,
+    });
+    if !meta.config.is_empty() {
+        entry["config"] = json!(meta.config);
+    }
+    if !meta.scripts.is_empty() {
+        entry["scripts"] = serde_json::to_value(&meta.scripts)?;
+    }
+    Ok(entry)
+}
+
+/// Paths of everything but directories in an archive, sorted, as `files.json` lists them.
+fn shipped_files(archive: &Path) -> Result<Vec<String>> {
+    let file = File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    let mut paths = Vec::new();
+    for entry in Archive::new(GzDecoder::new(file)).entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+        if let Some(path) = package_path(&entry.path()?)
+            && path != META_PATH
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Sign `path` as `<path>.asc` with gpg's default key, or `key`.
+fn sign_file(path: &Path, key: Option<&str>) -> Result<()> {
+    let mut signature_path = path.as_os_str().to_os_string();
+    signature_path.push(".asc");
+    let signature_path = PathBuf::from(signature_path);
+
+    let mut gpg = Command::new("gpg");
+    gpg.args(["--batch", "--yes", "--armor", "--detach-sign"]);
+    if let Some(key) = key {
+        gpg.args(["--local-user", key]);
+    }
+    let status = gpg
+        .arg("--output")
+        .arg(&signature_path)
+        .arg(path)
+        .status()
+        .context("Failed to run gpg")?;
+    if !status.success() {
+        bail!("gpg failed to sign {}", path.display());
+    }
+    print_success(&format!("Signed {}", signature_path.display()));
+    Ok(())
+}
+
+/// Write `index.json` and `files.json` for the `.pkg` files in `dir` from their embedded
+/// metadata, keeping the newest version of each package, and optionally sign them
+/// (with gpg's default key, or `key`).
+pub fn repo_build(dir: &Path, sign: bool, key: Option<&str>) -> Result<()> {
+    let mut archives: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pkg"))
+        .collect();
+    archives.sort();
+    if archives.is_empty() {
+        bail!("No .pkg files in {}", dir.display());
+    }
+
+    let mut packages: BTreeMap<String, (String, Value, &Path)> = BTreeMap::new();
+    for archive in &archives {
+        let Some(meta) = read_meta(archive)? else {
+            print_error(&format!("Skipping {}: it has no meta.toml", archive.display()));
+            continue;
+        };
+        validate_meta(&meta)
+            .with_context(|| format!("Invalid meta.toml in {}", archive.display()))?;
+
+        if let Some((newest, _, _)) = packages.get(&meta.name) {
+            if version::compare(newest, &meta.version) != Ordering::Less {
+                print_info(&format!("Skipping {} {}: {} is newer", meta.name, meta.version, newest));
+                continue;
+            }
+            print_info(&format!("Skipping {} {}: {} is newer", meta.name, newest, meta.version));
+        }
+        let entry = index_entry(&meta, archive)?;
+        packages.insert(meta.name.clone(), (meta.version.clone(), entry, archive));
+    }
+
+    let mut index = BTreeMap::new();
+    let mut files = BTreeMap::new();
+    for (name, (_, entry, archive)) in packages {
+        files.insert(name.clone(), shipped_files(archive)?);
+        index.insert(name, entry);
+    }
+
+    let index_path = dir.join("index.json");
+    let content = serde_json::to_string_pretty(&json!({ "packages": index }))?;
+    db::write_atomic(&index_path, content.as_bytes())?;
+    print_success(&format!(
+        "Wrote {} with {} package(s)",
+        index_path.display(),
+        index.len()
+    ));
+
+    // The file lists behind `anspm provides` and `command-not-found`.
+    let files_path = dir.join("files.json");
+    let content = serde_json::to_string(&json!({ "packages": files }))?;
+    db::write_atomic(&files_path, content.as_bytes())?;
+    print_success(&format!("Wrote {}", files_path.display()));
+
+    if sign {
+        sign_file(&index_path, key)?;
+        sign_file(&files_path, key)?;
+    }
+    Ok(())
+}
@@ -1,5 +1,8 @@
 use clap::{Parser, Subcommand};
 use colored::Colorize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
 
 #[derive(Parser)]
 #[command(name = "anspm")]
@@ -11,10 +14,24 @@ pub struct Args {
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Install a package
-    Install { name: String },
+    /// Install a package by name, or from a .pkg file or URL
+    Install {
+        name: String,
+        #[arg(long, help = "Do not run package install scripts")]
+        no_scripts: bool,
+        #[arg(long, help = "Refuse a package file without a valid detached signature (<file>.asc)")]
+        verify_sig: bool,
+    },
     /// Remove a package
-    Remove { name: String },
+    Remove {
+        name: String,
+        #[arg(long, conflicts_with = "force", help = "Also remove packages that depend on it")]
+        cascade: bool,
+        #[arg(long, help = "Remove even if other packages depend on it")]
+        force: bool,
+        #[arg(long, help = "Also delete config files, even edited ones")]
+        purge: bool,
+    },
     /// Update a package
     Update {
         #[arg(short, long)]
@@ -22,18 +39,103 @@ pub enum Commands {
     },
     /// Reinstall a package
     Reinstall { name: String },
+    /// Restore missing or modified files of a package from its archive
+    Repair { name: String },
     /// List installed packages
-    List,
+    List {
+        #[arg(long, help = "Only show dependencies nothing needs anymore")]
+        orphans: bool,
+    },
+    /// Change whether packages count as explicitly installed or as dependencies
+    Mark {
+        #[arg(required = true)]
+        names: Vec<String>,
+        #[arg(long, conflicts_with = "dep", required_unless_present = "dep")]
+        explicit: bool,
+        #[arg(long)]
+        dep: bool,
+    },
+    /// Remove dependencies that no installed package needs anymore
+    Autoremove,
+    /// Keep a package at its installed version or within a constraint (name@>=1.0,<2.0)
+    Hold {
+        spec: String,
+        #[arg(long, help = "Store the hold in /etc/anspm/anspm.toml")]
+        system: bool,
+    },
+    /// Release a hold
+    Unhold {
+        name: String,
+        #[arg(long, help = "Remove the hold from /etc/anspm/anspm.toml")]
+        system: bool,
+    },
     /// Search for packages
     Search { query: String },
+    /// Show details of an installed or available package
+    Info { name: String },
+    /// Find packages in the repositories that ship a file or command
+    Provides { query: String },
+    /// Suggest a package for a missing command (for shell hooks)
+    #[clap(hide = true)]
+    CommandNotFound { command: String },
+    /// Show which installed package owns a file
+    Owns { path: std::path::PathBuf },
+    /// List the files installed by a package
+    Files { name: String },
+    /// Check installed files for changes (all packages if none given)
+    Verify { names: Vec<String> },
     /// Clean cache
     CleanCache,
+    /// Build a .pkg from a directory holding meta.toml and the files to install
+    Build {
+        dir: std::path::PathBuf,
+        #[arg(short, long, help = "Where to write the package (default: <name>-<version>.pkg)")]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Generate index.json for a directory of .pkg files
+    RepoBuild {
+        dir: std::path::PathBuf,
+        #[arg(long, help = "Sign index.json with gpg, producing index.json.asc")]
+        sign: bool,
+        #[arg(long, requires = "sign", help = "gpg key to sign with (default: gpg's default key)")]
+        key: Option<String>,
+    },
+    /// Resolve a transaction interrupted by a crash or kill
+    Recover {
+        #[arg(long, conflicts_with = "back", required_unless_present = "back")]
+        forward: bool,
+        #[arg(long)]
+        back: bool,
+    },
     /// Repository operations
     #[command(subcommand)]
     Repo(RepoCommands),
+    /// Read or change anspm.toml settings
+    #[command(subcommand)]
+    Config(ConfigCommands),
 
-    #[clap(hide = true)]  // <-- easter egg, because why not?
-    Why,
+    /// Explain which installed packages pulled a package in
+    Why {
+        name: Option<String>,  // <-- without a name it's still an easter egg, because why not?
+    },
+}
+
+impl Commands {
+    /// Commands that change installed files or the package database and must hold the lock.
+    pub fn mutates_system(&self) -> bool {
+        matches!(
+            self,
+            Commands::Install { .. }
+                | Commands::Remove { .. }
+                | Commands::Reinstall { .. }
+                | Commands::Repair { .. }
+                | Commands::Mark { .. }
+                | Commands::Autoremove
+                | Commands::Update { .. }
+                | Commands::CleanCache
+                | Commands::Recover { .. }
+        )
+    }
 }
 
 #[derive(Subcommand)]
@@ -42,7 +144,9 @@ pub enum RepoCommands {
     Add {
         url: String,
         #[arg(short, long, help = "Custom repository name")]
-        name: Option<String>
+        name: Option<String>,
+        #[arg(long, help = "Add to /etc/anspm/repos.d for all users")]
+        system: bool
     },
     /// Remove a repository
     Remove {
@@ -58,15 +162,50 @@ pub enum RepoCommands {
     },
 }
 
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Print the effective value of a setting
+    Get {
+        key: String
+    },
+    /// Change a setting in anspm.toml
+    Set {
+        key: String,
+        value: String,
+        #[arg(long, help = "Write to /etc/anspm/anspm.toml instead of the user config")]
+        system: bool
+    },
+    /// List all settings with their effective values
+    List,
+}
+
+/// Drop anspm's own messages for the rest of this run, for output that ends up in the
+/// user's shell (`command-not-found`).
+pub fn set_quiet() {
+    QUIET.store(true, Ordering::SeqCst);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::SeqCst)
+}
 
 pub fn print_error(message: &str) {
+    if quiet() {
+        return;
+    }
     eprintln!("{}: {}", "ERROR".red().bold(), message);
 }
 
 pub fn print_success(message: &str) {
+    if quiet() {
+        return;
+    }
     println!("{}: {}", "SUCCESS".green().bold(), message);
 }
 
 pub fn print_info(message: &str) {
+    if quiet() {
+        return;
+    }
     println!("{}: {}", "INFO".blue().bold(), message);
 }
@@ -2,41 +2,417 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
 use anyhow::bail;
-use dirs;
 use serde_json::json;
 use crate::repo::RepoConfig;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
 
+const SYSTEM_CONFIG_DIR: &str = "/etc/anspm";
+const SETTINGS_FILE: &str = "anspm.toml";
+
+/// Every key understood by `anspm.toml`, in dotted form.
+/// `ANSPM_<KEY>` (dots replaced by underscores) overrides the file value.
+pub const SETTINGS_KEYS: &[&str] = &[
+    "cache_dir",
+    "db_path",
+    "root",
+    "parallelism",
+    "signature_policy",
+    "color",
+    "holds",
+    "network.proxy",
+    "network.no_proxy",
+    "network.ca_bundles",
+    "network.client_cert",
+    "network.client_key",
+    "network.connect_timeout",
+    "network.read_timeout",
+    "network.retries",
+    "network.retry_backoff_ms",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub db_path: Option<PathBuf>,
+    /// Directory packages are installed into.
+    pub root: PathBuf,
+    /// How many repositories are fetched at once.
+    pub parallelism: usize,
+    pub signature_policy: SignaturePolicy,
+    pub color: ColorMode,
+    /// Package name -> version constraint it is held to.
+    pub holds: BTreeMap<String, String>,
+    pub network: NetworkConfig,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            cache_dir: None,
+            db_path: None,
+            root: PathBuf::from("/"),
+            parallelism: 4,
+            signature_policy: SignaturePolicy::Required,
+            color: ColorMode::Auto,
+            holds: BTreeMap::new(),
+            network: NetworkConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignaturePolicy {
+    /// Refuse repositories whose index signature does not verify.
+    Required,
+    /// Print an error but keep going.
+    Warn,
+    /// Do not check signatures at all.
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+    pub ca_bundles: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>,
+    /// Seconds to wait for a connection to be established.
+    pub connect_timeout: u64,
+    /// Seconds to wait for a whole request, including the response body.
+    pub read_timeout: u64,
+    pub retries: u32,
+    pub retry_backoff_ms: u64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            proxy: None,
+            no_proxy: None,
+            ca_bundles: Vec::new(),
+            client_cert: None,
+            client_key: None,
+            connect_timeout: 10,
+            read_timeout: 300,
+            retries: 3,
+            retry_backoff_ms: 500,
+        }
+    }
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Effective settings: `/etc/anspm/anspm.toml`, then the user's `anspm.toml`,
+/// then `ANSPM_*` environment variables, each layer overriding the previous one.
+pub fn settings() -> Result<&'static Settings> {
+    if let Some(settings) = SETTINGS.get() {
+        return Ok(settings);
+    }
+
+    let mut merged = toml::Value::Table(toml::map::Map::new());
+    for path in settings_file_paths()? {
+        merge_toml(&mut merged, read_toml_file(&path)?);
+    }
+    for key in SETTINGS_KEYS {
+        let var = format!("ANSPM_{}", key.replace('.', "_").to_uppercase());
+        if let Ok(raw) = std::env::var(&var) {
+            set_dotted(&mut merged, key, parse_toml_scalar(&raw))?;
+        }
+    }
+
+    let settings: Settings = merged
+        .try_into()
+        .context("Invalid anspm configuration")?;
+    Ok(SETTINGS.get_or_init(|| settings))
+}
+
+pub fn get_network_config() -> Result<&'static NetworkConfig> {
+    Ok(&settings()?.network)
+}
+
+fn settings_file_paths() -> Result<Vec<PathBuf>> {
+    Ok(vec![
+        Path::new(SYSTEM_CONFIG_DIR).join(SETTINGS_FILE),
+        get_config_path(SETTINGS_FILE)?,
+    ])
+}
+
+fn read_toml_file(path: &Path) -> Result<toml::Value> {
+    if !path.exists() {
+        return Ok(toml::Value::Table(toml::map::Map::new()));
+    }
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn merge_toml(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_toml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Interpret a command-line or environment value as a TOML literal
+/// (`8`, `true`, `["a", "b"]`), falling back to a plain string.
+fn parse_toml_scalar(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Value>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|table| table.get("v").cloned())
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+fn set_dotted(root: &mut toml::Value, key: &str, value: toml::Value) -> Result<()> {
+    let mut current = root;
+    let mut parts = key.split('.').peekable();
+    while let Some(part) = parts.next() {
+        let table = current
+            .as_table_mut()
+            .ok_or_else(|| anyhow::anyhow!("'{}' is not a table", key))?;
+        if parts.peek().is_none() {
+            table.insert(part.to_string(), value);
+            return Ok(());
+        }
+        current = table
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::map::Map::new()));
+    }
+    Ok(())
+}
+
+fn get_dotted<'a>(root: &'a toml::Value, key: &str) -> Option<&'a toml::Value> {
+    key.split('.').try_fold(root, |value, part| value.get(part))
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if !SETTINGS_KEYS.contains(&key) {
+        bail!("Unknown configuration key '{}'", key);
+    }
+    Ok(())
+}
+
+fn format_setting(value: Option<&toml::Value>) -> String {
+    match value {
+        Some(toml::Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+        None => "(unset)".to_string(),
+    }
+}
+
+pub fn config_get(key: &str) -> Result<()> {
+    check_key(key)?;
+    let effective = toml::Value::try_from(settings()?)?;
+    println!("{}", format_setting(get_dotted(&effective, key)));
+    Ok(())
+}
+
+pub fn config_list() -> Result<()> {
+    let effective = toml::Value::try_from(settings()?)?;
+    for key in SETTINGS_KEYS {
+        println!("{} = {}", key, format_setting(get_dotted(&effective, key)));
+    }
+    Ok(())
+}
+
+pub fn config_set(key: &str, value: &str, system: bool) -> Result<()> {
+    check_key(key)?;
+    let path = edit_settings_file(system, |file| {
+        set_dotted(file, key, parse_toml_scalar(value))
+    })
+    .with_context(|| format!("Invalid value for '{}': {}", key, value))?;
+    crate::cli::print_success(&format!("Set {} in {}", key, path.display()));
+    Ok(())
+}
+
+/// Apply `edit` to the user's anspm.toml (or the system one), validate the result and save it.
+pub fn edit_settings_file(
+    system: bool,
+    edit: impl FnOnce(&mut toml::Value) -> Result<()>,
+) -> Result<PathBuf> {
+    let path = if system {
+        Path::new(SYSTEM_CONFIG_DIR).join(SETTINGS_FILE)
+    } else {
+        get_config_path(SETTINGS_FILE)?
+    };
+
+    let mut file = read_toml_file(&path)?;
+    edit(&mut file)?;
+    let _: Settings = file.clone().try_into()?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(&path, toml::to_string_pretty(&file)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+const REPOS_FILE: &str = "repos.list";
+const SYSTEM_REPOS_DIR: &str = "repos.d";
+const SYSTEM_TRIGGERS_DIR: &str = "triggers.d";
+
+/// Repositories that anspm should use: every `/etc/anspm/repos.d/*.toml` file plus
+/// the user's `repos.list`. A user entry with the same name overrides a system one.
 pub fn get_repos() -> Result<HashMap<String, RepoConfig>> {
-    let path = get_config_path("repos.list")?;
+    let user_path = get_config_path(REPOS_FILE)?;
+    if !user_path.exists() && load_system_repos()?.is_empty() {
+        create_default_repos(&user_path)?;
+    }
+
+    let repos = load_repos()?;
+    if repos.is_empty() {
+        bail!(
+            "No repositories configured in {} or {}",
+            system_repos_dir().display(),
+            user_path.display()
+        );
+    }
+
+    Ok(repos)
+}
+
+/// Merged repository list without creating defaults; may be empty.
+pub fn load_repos() -> Result<HashMap<String, RepoConfig>> {
+    let mut repos = load_system_repos()?;
+    repos.extend(load_user_repos()?);
+    Ok(repos)
+}
+
+pub fn system_repos_dir() -> PathBuf {
+    Path::new(SYSTEM_CONFIG_DIR).join(SYSTEM_REPOS_DIR)
+}
+
+pub fn system_triggers_dir() -> PathBuf {
+    Path::new(SYSTEM_CONFIG_DIR).join(SYSTEM_TRIGGERS_DIR)
+}
+
+pub fn load_system_repos() -> Result<HashMap<String, RepoConfig>> {
+    let dir = system_repos_dir();
+    let mut repos = HashMap::new();
+    if !dir.is_dir() {
+        return Ok(repos);
+    }
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    files.sort();
+
+    for path in files {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let file_repos: HashMap<String, RepoConfig> = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        for (name, mut repo) in file_repos {
+            repo.source = path.clone();
+            repos.insert(name, repo);
+        }
+    }
+
+    Ok(repos)
+}
+
+pub fn load_user_repos() -> Result<HashMap<String, RepoConfig>> {
+    let path = get_config_path(REPOS_FILE)?;
     if !path.exists() {
-        create_default_repos(&path)?;
+        return Ok(HashMap::new());
     }
     let content = fs::read_to_string(&path)
-        .context("Failed to read repos.list")?;
-    
+        .with_context(|| format!("Failed to read {}", path.display()))?;
     let content = content.trim();
-    
     if content.is_empty() {
-        bail!("repos.list is empty");
+        return Ok(HashMap::new());
     }
 
-    let repos: HashMap<String, RepoConfig> = serde_json::from_str(&content)
-        .context("Failed to parse repos.list as JSON")?;
-    
-    if repos.is_empty() {
-        bail!("No repositories configured in repos.list");
+    let mut repos: HashMap<String, RepoConfig> = serde_json::from_str(content)
+        .with_context(|| format!("Failed to parse {} as JSON", path.display()))?;
+    for repo in repos.values_mut() {
+        repo.source = path.clone();
     }
-
     Ok(repos)
 }
 
+pub fn save_user_repos(repos: &HashMap<String, RepoConfig>) -> Result<()> {
+    let path = get_config_path(REPOS_FILE)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(repos)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write a repository to its own file in `/etc/anspm/repos.d`.
+pub fn save_system_repo(name: &str, repo: &RepoConfig) -> Result<PathBuf> {
+    let dir = system_repos_dir();
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(format!("{}.toml", name));
+    let file: HashMap<&str, &RepoConfig> = HashMap::from([(name, repo)]);
+    fs::write(&path, toml::to_string_pretty(&file)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Drop a repository from the system file that defines it, deleting the file once empty.
+pub fn remove_system_repo(name: &str, path: &Path) -> Result<()> {
+    let mut file = match read_toml_file(path)? {
+        toml::Value::Table(table) => table,
+        _ => bail!("{} is not a table", path.display()),
+    };
+    file.remove(name);
+    if file.is_empty() {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
+    } else {
+        fs::write(path, toml::to_string_pretty(&file)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
 pub fn get_tracking_file_path() -> Result<PathBuf> {
+    let settings = settings()?;
+    if let Some(path) = &settings.db_path {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create database directory")?;
+        }
+        return Ok(path.clone());
+    }
+
     #[cfg(unix)]
     {
         use nix::unistd::Uid;
         if Uid::effective().is_root() {
-            let path = PathBuf::from("/var/lib/anspm/installed.db");
+            let path = settings.root.join("var/lib/anspm/installed.db");
             if let Some(parent) = path.parent() {
                 fs::create_dir_all(parent).context("Failed to create config directory")?;
             }
@@ -54,22 +430,7 @@ pub fn get_tracking_file_path() -> Result<PathBuf> {
     Ok(path)
 }
 
-pub fn read_tracking_file() -> Result<serde_json::Value> {
-    let path = get_tracking_file_path()?;
-    if !path.exists() {
-        return Ok(serde_json::json!({}));
-    }
-    let content = fs::read_to_string(&path).context("Failed to read tracking file")?;
-    serde_json::from_str(&content).context("Failed to parse tracking file")
-}
-
-pub fn write_tracking_file(data: &serde_json::Value) -> Result<()> {
-    let path = get_tracking_file_path()?;
-    let content = serde_json::to_string_pretty(data).context("Failed to serialize tracking data")?;
-    fs::write(&path, content).context("Failed to write tracking file")
-}
-
-fn get_config_path(filename: &str) -> Result<PathBuf> {
+pub fn get_config_path(filename: &str) -> Result<PathBuf> {
     let config_dir = dirs::config_dir()
     .ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?
     .join("anspm");
@@ -103,9 +464,12 @@ fn create_default_repos(path: &Path) -> Result<()> {
 }
 
 pub fn get_cache_dir() -> Result<PathBuf> {
-    let cache_dir = dirs::cache_dir()
-    .ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?
-    .join("anspm/pkgs");
+    let cache_dir = match &settings()?.cache_dir {
+        Some(dir) => dir.clone(),
+        None => dirs::cache_dir()
+            .ok_or_else(|| anyhow::anyhow!("Could not find cache directory"))?
+            .join("anspm/pkgs"),
+    };
 
     if !cache_dir.exists() {
         fs::create_dir_all(&cache_dir)?;
@@ -113,3 +477,59 @@ pub fn get_cache_dir() -> Result<PathBuf> {
 
     Ok(cache_dir)
 }
+
+/// Map an absolute package path (as stored in the tracking file) onto the configured root.
+pub fn root_path(path: &str) -> Result<PathBuf> {
+    Ok(settings()?.root.join(path.trim_start_matches('/')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_tables_key_by_key() {
+        let mut base: toml::Value = toml::from_str(
+            "jobs = 4\n[holds]\nfoo = \"=1.0\"\n[http]\nretries = 3\ntimeout = 10",
+        )
+        .unwrap();
+        let overlay: toml::Value =
+            toml::from_str("jobs = 8\n[holds]\nbar = \">=2\"\n[http]\nretries = 5").unwrap();
+
+        merge_toml(&mut base, overlay);
+        let expected: toml::Value = toml::from_str(
+            "jobs = 8\n[holds]\nfoo = \"=1.0\"\nbar = \">=2\"\n[http]\nretries = 5\ntimeout = 10",
+        )
+        .unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn overlay_replaces_arrays_and_mismatched_types() {
+        let mut base: toml::Value = toml::from_str("keys = [\"a\", \"b\"]\nproxy = \"x\"").unwrap();
+        let overlay: toml::Value = toml::from_str("keys = [\"c\"]\n[proxy]\nurl = \"y\"").unwrap();
+
+        merge_toml(&mut base, overlay);
+        let expected: toml::Value = toml::from_str("keys = [\"c\"]\n[proxy]\nurl = \"y\"").unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn parses_scalars_as_toml_literals() {
+        assert_eq!(parse_toml_scalar("8"), toml::Value::Integer(8));
+        assert_eq!(parse_toml_scalar("true"), toml::Value::Boolean(true));
+        assert_eq!(
+            parse_toml_scalar("[\"a\", \"b\"]"),
+            toml::Value::Array(vec!["a".into(), "b".into()])
+        );
+        assert_eq!(parse_toml_scalar("\"quoted\""), toml::Value::String("quoted".into()));
+    }
+
+    #[test]
+    fn falls_back_to_plain_strings() {
+        assert_eq!(parse_toml_scalar("never"), toml::Value::String("never".into()));
+        assert_eq!(parse_toml_scalar("1.2.3"), toml::Value::String("1.2.3".into()));
+        assert_eq!(parse_toml_scalar("/var/cache"), toml::Value::String("/var/cache".into()));
+        assert_eq!(parse_toml_scalar(""), toml::Value::String(String::new()));
+    }
+}
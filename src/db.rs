@@ -0,0 +1,429 @@
+use crate::cli::print_info;
+use crate::config::get_tracking_file_path;
+use crate::lock;
+use crate::pkg::package_path;
+use crate::repo::PackageInfo;
+use crate::scripts::Scripts;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the installed.db layout written by this build.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Database {
+    pub schema_version: u32,
+    pub packages: BTreeMap<String, InstalledPackage>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database {
+            schema_version: SCHEMA_VERSION,
+            packages: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstalledPackage {
+    pub version: String,
+    /// Name of the repository the package was installed from, if known.
+    pub repo: Option<String>,
+    pub reason: InstallReason,
+    /// Seconds since the Unix epoch; 0 when migrated from a DB that did not record it.
+    pub install_date: u64,
+    pub files: Vec<FileEntry>,
+    pub meta: PackageMeta,
+    /// File name of the archive in the package cache, used to repair the install.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+    /// Path or URL of the `.pkg` file, for packages installed from a file instead of a repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallReason {
+    /// Requested by the user.
+    Explicit,
+    /// Pulled in to satisfy another package.
+    Dependency,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub path: String,
+    /// Unknown for entries migrated from DBs that only stored paths.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<FileKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Where a symlink points.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// A directory that existed before the package was installed (e.g. `/usr/bin`);
+    /// removing the package never removes it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shared: bool,
+    /// A config file: kept when edited instead of being overwritten or deleted.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub config: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+impl FileKind {
+    pub fn of(metadata: &fs::Metadata) -> FileKind {
+        let file_type = metadata.file_type();
+        if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Directory
+        } else {
+            FileKind::File
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageMeta {
+    pub name: String,
+    pub version: String,
+    pub arch: String,
+    pub os: String,
+    pub deps: Vec<String>,
+    pub author: String,
+    pub license: String,
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub config: Vec<String>,
+    /// Kept so the removal hooks can still run once the package archive is gone.
+    #[serde(default, skip_serializing_if = "Scripts::is_empty")]
+    pub scripts: Scripts,
+}
+
+impl From<&PackageInfo> for PackageMeta {
+    fn from(pkg: &PackageInfo) -> Self {
+        PackageMeta {
+            name: pkg.name.clone(),
+            version: pkg.version.clone(),
+            arch: pkg.arch.clone(),
+            os: pkg.os.clone(),
+            deps: pkg.deps.clone(),
+            author: pkg.author.clone(),
+            license: pkg.license.clone(),
+            description: pkg.description.clone(),
+            config: pkg.config.clone(),
+            scripts: pkg.scripts.clone(),
+        }
+    }
+}
+
+impl FileEntry {
+    /// An entry that knows nothing but its path.
+    pub fn bare(path: &str) -> FileEntry {
+        FileEntry {
+            path: path.to_string(),
+            kind: None,
+            sha256: None,
+            size: None,
+            mode: None,
+            target: None,
+            shared: false,
+            config: false,
+        }
+    }
+
+    /// Record `path` (as seen by the package) using the file found at `on_disk`.
+    pub fn capture(path: &str, on_disk: &Path) -> Result<FileEntry> {
+        let mut entry = FileEntry::bare(path);
+
+        let Ok(metadata) = fs::symlink_metadata(on_disk) else {
+            return Ok(entry);
+        };
+
+        let kind = FileKind::of(&metadata);
+        entry.kind = Some(kind);
+        match kind {
+            FileKind::File => {
+                entry.sha256 = Some(sha256_file(on_disk)?);
+                entry.size = Some(metadata.len());
+            }
+            FileKind::Symlink => {
+                entry.target = Some(fs::read_link(on_disk)?.to_string_lossy().into_owned());
+            }
+            FileKind::Directory => {}
+        }
+
+        // A symlink's own mode is meaningless on Linux, so only record it for real files and dirs.
+        #[cfg(unix)]
+        if kind != FileKind::Symlink {
+            use std::os::unix::fs::PermissionsExt;
+            entry.mode = Some(metadata.permissions().mode() & 0o7777);
+        }
+
+        Ok(entry)
+    }
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn load() -> Result<Database> {
+    let path = get_tracking_file_path()?;
+    if !path.exists() {
+        return Ok(Database::default());
+    }
+
+    let content = fs::read_to_string(&path).context("Failed to read tracking file")?;
+    let mut raw: Value = if content.trim().is_empty() {
+        json!({})
+    } else {
+        serde_json::from_str(&content).context("Failed to parse tracking file")?
+    };
+
+    let found = raw
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as u32;
+    if found > SCHEMA_VERSION {
+        bail!(
+            "{} uses schema v{}, but this anspm only understands up to v{}; please upgrade anspm",
+            path.display(),
+            found,
+            SCHEMA_VERSION
+        );
+    }
+
+    for version in found..SCHEMA_VERSION {
+        raw = migrate(version, raw)
+            .with_context(|| format!("Failed to migrate tracking file from schema v{}", version))?;
+    }
+
+    let db: Database = serde_json::from_value(raw).context("Failed to parse tracking file")?;
+
+    // Read-only commands run without the lock and must not race a writer; they keep
+    // migrating in memory until a locked command saves the result.
+    if found < SCHEMA_VERSION && lock::held() {
+        save(&db)?;
+        print_info(&format!(
+            "Migrated {} from schema v{} to v{}",
+            path.display(),
+            found,
+            SCHEMA_VERSION
+        ));
+    }
+
+    Ok(db)
+}
+
+/// Replace installed.db, keeping the previous contents as `installed.db.bak`.
+pub fn save(db: &Database) -> Result<()> {
+    let path = get_tracking_file_path()?;
+    let content = serde_json::to_string_pretty(db).context("Failed to serialize tracking data")?;
+
+    if path.exists() {
+        fs::copy(&path, path.with_extension("db.bak"))
+            .context("Failed to back up tracking file")?;
+    }
+
+    write_atomic(&path, content.as_bytes()).context("Failed to write tracking file")?;
+    save_index(db)
+}
+
+/// Reverse index of installed.db: package path -> packages that list it.
+pub type PathIndex = BTreeMap<String, Vec<String>>;
+
+fn index_path() -> Result<PathBuf> {
+    Ok(get_tracking_file_path()?.with_extension("idx"))
+}
+
+fn build_index(db: &Database) -> PathIndex {
+    let mut index = PathIndex::new();
+    for (name, pkg) in &db.packages {
+        for file in &pkg.files {
+            index.entry(file.path.clone()).or_default().push(name.clone());
+        }
+    }
+    index
+}
+
+fn save_index(db: &Database) -> Result<()> {
+    let content = serde_json::to_string(&build_index(db)).context("Failed to serialize path index")?;
+    write_atomic(&index_path()?, content.as_bytes()).context("Failed to write path index")
+}
+
+/// Load the path index, rebuilding it if it is missing or older than installed.db.
+pub fn load_index() -> Result<PathIndex> {
+    let db_path = get_tracking_file_path()?;
+    let index_path = index_path()?;
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+
+    if index_path.exists() && modified(&index_path) >= modified(&db_path) {
+        let content = fs::read_to_string(&index_path).context("Failed to read path index")?;
+        if let Ok(index) = serde_json::from_str(&content) {
+            return Ok(index);
+        }
+    }
+
+    let db = load()?;
+    if db_path.exists() && lock::held() {
+        save_index(&db)?;
+    }
+    Ok(build_index(&db))
+}
+
+/// Write `contents` to a temporary file next to `path`, fsync it and rename it into place,
+/// so readers see either the old file or the new one, never a truncated mix.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    {
+        let mut tmp = File::create(&tmp_path)
+            .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+        tmp.write_all(contents)?;
+        tmp.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+
+    // A bare file name has an empty parent, meaning the current directory.
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+        File::open(parent)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("Failed to sync {}", parent.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Upgrade a raw DB from schema `from` to `from + 1`.
+fn migrate(from: u32, raw: Value) -> Result<Value> {
+    match from {
+        0 => migrate_v0(raw),
+        _ => bail!("no migration from schema v{}", from),
+    }
+}
+
+/// v0 was a bare `{ name: { version, files: [path], meta } }` object.
+fn migrate_v0(raw: Value) -> Result<Value> {
+    let Value::Object(old) = raw else {
+        bail!("expected a JSON object");
+    };
+
+    let mut packages = serde_json::Map::new();
+    for (name, info) in old {
+        let version = info["version"].as_str().unwrap_or("unknown");
+        // v0 stored archive paths as-is (`/./usr/bin/foo`, `/usr/`, even `/`).
+        let mut files: Vec<Value> = Vec::new();
+        for path in info["files"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+            if let Some(path) = package_path(Path::new(path)) {
+                let entry = json!({ "path": path });
+                if !files.contains(&entry) {
+                    files.push(entry);
+                }
+            }
+        }
+        let meta = &info["meta"];
+        let meta_str = |key: &str, default: &str| {
+            meta[key].as_str().unwrap_or(default).to_string()
+        };
+
+        packages.insert(name.clone(), json!({
+            "version": version,
+            "repo": null,
+            "reason": "explicit",
+            "install_date": 0,
+            "files": files,
+            "meta": {
+                "name": meta_str("name", &name),
+                "version": meta_str("version", version),
+                "arch": meta_str("arch", "any"),
+                "os": meta_str("os", "all"),
+                "deps": meta["deps"].as_array().cloned().unwrap_or_default(),
+                "author": meta_str("author", "unknown"),
+                "license": meta_str("license", "unknown"),
+                "description": meta_str("description", "No description"),
+            }
+        }));
+    }
+
+    Ok(json!({
+        "schema_version": 1,
+        "packages": packages,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_v0_paths_to_normalized_entries() {
+        let v0 = json!({
+            "foo": {
+                "version": "1.0",
+                "files": ["/./usr/bin/foo", "/", "/usr/", "/usr/bin/foo", "/usr"],
+                "meta": { "name": "foo", "version": "1.0", "deps": ["bar"] },
+            }
+        });
+
+        let db: Database = serde_json::from_value(migrate_v0(v0).unwrap()).unwrap();
+        assert_eq!(db.schema_version, 1);
+        let foo = &db.packages["foo"];
+        let paths: Vec<&str> = foo.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["/usr/bin/foo", "/usr"]);
+        assert!(foo.files.iter().all(|f| f.sha256.is_none() && !f.shared));
+        assert_eq!(foo.reason, InstallReason::Explicit);
+        assert_eq!(foo.meta.deps, ["bar"]);
+        assert_eq!(foo.meta.os, "all");
+    }
+
+    #[test]
+    fn migrates_entries_missing_fields() {
+        let v0 = json!({ "bare": { "files": [] } });
+
+        let db: Database = serde_json::from_value(migrate_v0(v0).unwrap()).unwrap();
+        let bare = &db.packages["bare"];
+        assert_eq!(bare.version, "unknown");
+        assert_eq!(bare.meta.name, "bare");
+        assert!(bare.files.is_empty());
+    }
+
+    #[test]
+    fn rejects_v0_that_is_not_an_object() {
+        assert!(migrate_v0(json!(["foo"])).is_err());
+    }
+}
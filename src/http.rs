@@ -0,0 +1,102 @@
+use crate::cli::print_info;
+use crate::config::{get_network_config, NetworkConfig};
+use anyhow::{Context, Result};
+use reqwest::blocking::{Client, Response};
+use reqwest::{Certificate, Identity, NoProxy, Proxy, StatusCode};
+use std::fs;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// Shared HTTP client configured from the `[network]` section of `anspm.toml`.
+pub fn client() -> Result<&'static Client> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+    let client = build_client(get_network_config()?)?;
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+fn build_client(config: &NetworkConfig) -> Result<Client> {
+    let mut builder = Client::builder()
+        .user_agent(concat!("anspm/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(Duration::from_secs(config.connect_timeout))
+        .timeout(Duration::from_secs(config.read_timeout));
+
+    if let Some(proxy_url) = &config.proxy {
+        let proxy = Proxy::all(proxy_url)
+            .with_context(|| format!("Invalid proxy URL: {}", proxy_url))?
+            .no_proxy(config.no_proxy.as_deref().and_then(NoProxy::from_string));
+        builder = builder.proxy(proxy);
+    }
+
+    for bundle in &config.ca_bundles {
+        let pem = fs::read(bundle)
+            .with_context(|| format!("Failed to read CA bundle {}", bundle.display()))?;
+        let certs = Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Failed to parse CA bundle {}", bundle.display()))?;
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    match (&config.client_cert, &config.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert = fs::read(cert_path)
+                .with_context(|| format!("Failed to read client certificate {}", cert_path.display()))?;
+            let key = fs::read(key_path)
+                .with_context(|| format!("Failed to read client key {}", key_path.display()))?;
+            let identity = Identity::from_pkcs8_pem(&cert, &key)
+                .context("Failed to load client certificate")?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => anyhow::bail!("Both client_cert and client_key must be set to use a client certificate"),
+    }
+
+    builder.build().context("Failed to build HTTP client")
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// GET `url`, retrying connection errors and 5xx/429 responses with exponential backoff.
+pub fn get(url: &str) -> Result<Response> {
+    let config = get_network_config()?;
+    let client = client()?;
+    let mut attempt = 0;
+
+    loop {
+        let error = match client.get(url).send() {
+            Ok(response) if response.status().is_success() => return Ok(response),
+            Ok(response) if !is_retryable(response.status()) => {
+                anyhow::bail!("{} returned status: {}", url, response.status())
+            }
+            Ok(response) => anyhow::anyhow!("{} returned status: {}", url, response.status()),
+            Err(e) => anyhow::Error::new(e).context(format!("Failed to fetch {}", url)),
+        };
+
+        if attempt >= config.retries {
+            return Err(error);
+        }
+
+        let delay = config.retry_backoff_ms.saturating_mul(1 << attempt.min(16));
+        attempt += 1;
+        print_info(&format!(
+            "{:#}; retrying in {} ms ({}/{})",
+            error, delay, attempt, config.retries
+        ));
+        thread::sleep(Duration::from_millis(delay));
+    }
+}
+
+pub fn get_bytes(url: &str) -> Result<Vec<u8>> {
+    let response = get(url)?;
+    Ok(response
+        .bytes()
+        .with_context(|| format!("Failed to read response from {}", url))?
+        .to_vec())
+}
@@ -0,0 +1,132 @@
+use crate::config::get_tracking_file_path;
+use crate::db::{self, InstalledPackage};
+use anyhow::{Context, Result};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Install,
+    Remove,
+}
+
+/// Write-ahead record of the package operation in progress.
+/// It is written before anything on disk changes and deleted once installed.db is updated,
+/// so its presence at startup means the previous run was cut short.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Journal {
+    pub operation: Operation,
+    pub package: String,
+    pub version: String,
+    pub started: u64,
+    /// Package paths the operation writes or deletes.
+    pub files: Vec<String>,
+    /// Paths that existed before an install started; a rollback leaves them alone.
+    #[serde(default)]
+    pub preexisting: Vec<String>,
+    /// Edited config files an install unpacks as `<path>.anspmnew` instead.
+    #[serde(default)]
+    pub diverted: Vec<String>,
+    /// A removal that deletes edited config files rather than saving them.
+    #[serde(default)]
+    pub purge: bool,
+    /// Files an install overwrites or deletes, copied to `backup_dir` first so a rollback
+    /// can put them back.
+    #[serde(default)]
+    pub backed_up: Vec<String>,
+    /// Database entry of the version an install replaces, restored by a rollback.
+    #[serde(default)]
+    pub previous: Option<InstalledPackage>,
+    /// Cached archive an install can be replayed from.
+    pub archive: Option<PathBuf>,
+    /// Checksum the index published for `archive`, checked again before a replay.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Database entry to record once an install completes (its file list is filled in then).
+    pub entry: Option<InstalledPackage>,
+}
+
+fn journal_path() -> Result<PathBuf> {
+    Ok(get_tracking_file_path()?.with_file_name("journal.json"))
+}
+
+pub fn begin(journal: &Journal) -> Result<()> {
+    let content = serde_json::to_string_pretty(journal).context("Failed to serialize journal")?;
+    db::write_atomic(&journal_path()?, content.as_bytes()).context("Failed to write journal")
+}
+
+/// Where an install keeps copies of the files it replaces until it is finished or undone.
+pub fn backup_dir() -> Result<PathBuf> {
+    Ok(get_tracking_file_path()?.with_file_name("journal.backup"))
+}
+
+pub fn clear() -> Result<()> {
+    let path = journal_path()?;
+    if path.exists() {
+        fs::remove_file(&path).context("Failed to remove journal")?;
+    }
+    let backups = backup_dir()?;
+    if backups.exists() {
+        fs::remove_dir_all(&backups).context("Failed to remove journal backups")?;
+    }
+    Ok(())
+}
+
+/// The journal left behind by an interrupted run, if any.
+pub fn pending() -> Result<Option<Journal>> {
+    let path = journal_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path).context("Failed to read journal")?;
+    let journal = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(journal))
+}
+
+extern "C" fn on_interrupt(_: nix::libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Ctrl-C handling while a journaled step runs; the previous handlers come back on drop.
+pub struct DeferredInterrupts {
+    previous: Vec<(Signal, SigAction)>,
+}
+
+/// Turn Ctrl-C into a flag checked between steps, so the current step can be
+/// finished or undone instead of leaving half-written files behind. Only meant around
+/// such steps: anywhere else (a download, a prompt) Ctrl-C should just stop anspm.
+pub fn defer_interrupts() -> Result<DeferredInterrupts> {
+    let action = SigAction::new(
+        SigHandler::Handler(on_interrupt),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    let mut deferred = DeferredInterrupts { previous: Vec::new() };
+    for signal in [Signal::SIGINT, Signal::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic.
+        let previous =
+            unsafe { sigaction(signal, &action) }.context("Failed to install signal handler")?;
+        deferred.previous.push((signal, previous));
+    }
+    Ok(deferred)
+}
+
+impl Drop for DeferredInterrupts {
+    fn drop(&mut self) {
+        for (signal, previous) in &self.previous {
+            // SAFETY: puts back the handler that was installed before.
+            unsafe { sigaction(*signal, previous) }.ok();
+        }
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
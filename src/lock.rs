@@ -0,0 +1,68 @@
+use crate::config::get_tracking_file_path;
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static HELD: AtomicBool = AtomicBool::new(false);
+
+/// Exclusive lock on the package database, released when dropped.
+pub struct Lock {
+    file: File,
+    path: PathBuf,
+}
+
+pub fn acquire() -> Result<Lock> {
+    let path = get_tracking_file_path()?.with_file_name("anspm.lock");
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+
+    match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(()) => {}
+        Err(Errno::EWOULDBLOCK) => {
+            let mut pid = String::new();
+            file.read_to_string(&mut pid).ok();
+            let pid = pid.trim();
+            if pid.is_empty() {
+                bail!("another anspm is running (lock held on {})", path.display());
+            }
+            bail!("another anspm is running (pid {})", pid);
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to lock {}", path.display()));
+        }
+    }
+
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    write!(file, "{}", std::process::id())?;
+    file.sync_all()?;
+
+    HELD.store(true, Ordering::SeqCst);
+    Ok(Lock { file, path })
+}
+
+/// Whether this process holds the lock, i.e. may write installed.db and its index.
+pub fn held() -> bool {
+    HELD.load(Ordering::SeqCst)
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        HELD.store(false, Ordering::SeqCst);
+        // Clear the pid so a stale file is not mistaken for a live holder;
+        // the flock itself goes away with the descriptor.
+        if self.file.set_len(0).is_err() {
+            eprintln!("warning: could not clear {}", self.path.display());
+        }
+    }
+}
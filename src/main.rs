@@ -1,31 +1,99 @@
+mod builder;
 mod cli;
 mod config;
+mod db;
+mod http;
+mod journal;
+mod lock;
 mod pkg;
 mod repo;
+#[cfg(target_os = "linux")]
+mod sandbox;
+mod scripts;
+mod triggers;
+mod version;
 
 use anyhow::Result;
 use clap::Parser;
 
 fn main() -> Result<()> {
     let args = cli::Args::parse();
-    match args.command {
-        cli::Commands::Install { name } => pkg::install(&name, true),
-        cli::Commands::Remove { name } => pkg::remove(&name),
+    if let Ok(settings) = config::settings() {
+        match settings.color {
+            config::ColorMode::Always => colored::control::set_override(true),
+            config::ColorMode::Never => colored::control::set_override(false),
+            config::ColorMode::Auto => {}
+        }
+    }
+    let mutates = args.command.mutates_system();
+    let _lock = if mutates {
+        let lock = lock::acquire()?;
+        if !matches!(args.command, cli::Commands::Recover { .. }) {
+            pkg::resume_interrupted()?;
+        }
+        Some(lock)
+    } else {
+        None
+    };
+
+    let result = match args.command {
+        cli::Commands::Install { name, no_scripts, verify_sig } => {
+            if no_scripts {
+                scripts::disable();
+            }
+            if pkg::is_package_file(&name) {
+                pkg::install_file(&name, verify_sig)
+            } else {
+                pkg::install(&name, true)
+            }
+        }
+        cli::Commands::Remove { name, cascade, force, purge } => {
+            pkg::remove(&name, cascade, force, purge)
+        }
         cli::Commands::Reinstall { name } => pkg::reinstall(&name),
-        cli::Commands::List => pkg::list(),
+        cli::Commands::Repair { name } => pkg::repair(&name),
+        cli::Commands::List { orphans } => pkg::list(orphans),
+        cli::Commands::Mark { names, explicit, .. } => {
+            let reason = if explicit { db::InstallReason::Explicit } else { db::InstallReason::Dependency };
+            pkg::mark(&names, reason)
+        }
+        cli::Commands::Autoremove => pkg::autoremove(),
+        cli::Commands::Hold { spec, system } => pkg::hold(&spec, system),
+        cli::Commands::Unhold { name, system } => pkg::unhold(&name, system),
         cli::Commands::Search { query } => repo::search(&query),
+        cli::Commands::Info { name } => pkg::info(&name),
+        cli::Commands::Provides { query } => repo::provides(&query),
+        cli::Commands::CommandNotFound { command } => repo::command_not_found(&command),
+        cli::Commands::Owns { path } => pkg::owns(&path),
+        cli::Commands::Files { name } => pkg::files(&name),
+        cli::Commands::Verify { names } => pkg::verify(&names),
         cli::Commands::Update { only } => pkg::update(only.as_deref()),
         cli::Commands::CleanCache => pkg::clean_cache(),
+        cli::Commands::Build { dir, output } => builder::build(&dir, output.as_deref()),
+        cli::Commands::RepoBuild { dir, sign, key } => builder::repo_build(&dir, sign, key.as_deref()),
+        cli::Commands::Recover { forward, .. } => pkg::recover(forward),
         cli::Commands::Repo(subcmd) => match subcmd {
-            cli::RepoCommands::Add { url, name } => repo::repo_add(&url, name.as_deref()),
+            cli::RepoCommands::Add { url, name, system } => repo::repo_add(&url, name.as_deref(), system),
             cli::RepoCommands::Remove { name } => repo::repo_remove(&name),
             cli::RepoCommands::List => repo::repo_list(),
             cli::RepoCommands::UpdateKeys => repo::repo_update_keys(),
             cli::RepoCommands::Verify { name } => repo::repo_verify(&name),
         },
-        cli::Commands::Why => {
+        cli::Commands::Config(subcmd) => match subcmd {
+            cli::ConfigCommands::Get { key } => config::config_get(&key),
+            cli::ConfigCommands::Set { key, value, system } => config::config_set(&key, &value, system),
+            cli::ConfigCommands::List => config::config_list(),
+        },
+        cli::Commands::Why { name: Some(name) } => pkg::why(&name),
+        cli::Commands::Why { name: None } => {
             println!("Because you deserve a simple package manager. 💖");
             Ok(())
         }
+    };
+
+    // Triggers run once for everything the command touched, even if it stopped partway.
+    if mutates && let Err(e) = triggers::run_pending() {
+        cli::print_error(&format!("{:#}", e));
     }
+    result
 }
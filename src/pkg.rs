@@ -1,139 +1,847 @@
 use crate::cli::{print_error, print_info, print_success};
-use crate::config::{read_tracking_file, write_tracking_file};
-use crate::repo;
-use anyhow::{Result};
+use crate::db::{self, FileEntry, FileKind, InstallReason, InstalledPackage};
+use crate::http;
+use crate::journal::{self, Journal, Operation};
+use crate::repo::{self, Meta, PackageInfo};
+use crate::scripts::{self, Hook};
+use crate::triggers;
+use anyhow::{bail, Context, Result};
 use colored::Colorize;
-use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::{Path};
-use crate::repo::PackageInfo;
-use crate::config::get_cache_dir;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use crate::config::{edit_settings_file, get_cache_dir, root_path, settings};
+use crate::version::{self, Constraint};
+use std::cmp::Ordering;
 use std::fs::File;
 use flate2::read::GzDecoder;
 use tar::Archive;
 use url::Url;
 
-fn update_package_db(pkg_name: &str, pkg: &PackageInfo, files: &[String]) -> Result<()> {
-    let mut db = read_tracking_file()?;
-    db[pkg_name] = json!({
-        "version": pkg.version,
-        "files": files,
-        "meta": {
-            "name": pkg.name,
-            "version": pkg.version,
-            "arch": pkg.arch,
-            "os": pkg.os,
-            "deps": pkg.deps,
-            "author": pkg.author,
-            "license": pkg.license,
-            "description": pkg.description
-        }
-    });
-    write_tracking_file(&db)
-}
-
-fn download_pkg_with_cache(pkg_name: &str, url: &str) -> Result<Vec<u8>> {
+fn update_package_db(pkg_name: &str, entry: InstalledPackage) -> Result<()> {
+    let mut db = db::load()?;
+    db.packages.insert(pkg_name.to_string(), entry);
+    db::save(&db)
+}
+
+/// The archive of `pkg`, from the cache or downloaded into it, checked against the
+/// checksum its index entry publishes either way.
+fn download_pkg_with_cache(pkg: &PackageInfo) -> Result<PathBuf> {
+    let url = package_url(pkg)?;
     let cache_dir = get_cache_dir()?;
     let cached_path = cache_dir.join(format!(
         "{}-{}",
-        pkg_name,
-        url.split('/').last().unwrap_or("pkg.pkg")
+        pkg.name,
+        url.split('/').next_back().unwrap_or("pkg.pkg")
     ));
 
     if cached_path.exists() {
         print_info(&format!("Using cached package: {}", cached_path.display()));
-        return Ok(fs::read(&cached_path)?);
+    } else {
+        print_info(&format!("Downloading package: {}", url));
+        let pkg_data = http::get_bytes(&url)?;
+
+        db::write_atomic(&cached_path, &pkg_data)?;
+        print_info(&format!("Cached package at: {}", cached_path.display()));
+    }
+
+    check_checksum(&pkg.name, pkg.sha256.as_deref(), &cached_path)?;
+    Ok(cached_path)
+}
+
+fn package_url(pkg: &PackageInfo) -> Result<String> {
+    // Join against the repository as a directory, so `foo.pkg` stays under its subpath.
+    let base = Url::parse(&format!("{}/", pkg.base_url.trim_end_matches('/')))
+        .with_context(|| format!("Invalid repository URL: {}", pkg.base_url))?;
+    let full_url = base
+        .join(&pkg.url)
+        .with_context(|| format!("Invalid package URL: {}", pkg.url))?;
+    Ok(full_url.to_string())
+}
+
+/// The archive an installed package came from: the cached copy if it is still there,
+/// otherwise downloaded again provided the repository still offers the same version.
+fn archive_for_installed(pkg_name: &str, installed: &InstalledPackage) -> Result<PathBuf> {
+    // A repository package goes through the download below even when cached, so its
+    // archive is checked against the index like one about to be installed.
+    if let Some(source) = &installed.source {
+        if let Some(name) = &installed.archive {
+            let cached = get_cache_dir()?.join(name);
+            if cached.exists() {
+                print_info(&format!("Using cached package: {}", cached.display()));
+                return Ok(cached);
+            }
+        }
+        bail!(
+            "The cached archive of {} is gone; install it again with `anspm install {}`",
+            pkg_name,
+            source
+        );
+    }
+
+    let pkg = repo::find_package(pkg_name)?;
+    if pkg.version != installed.version {
+        bail!(
+            "{} {} is no longer available (repository has {}); use `anspm update {}` instead",
+            pkg_name,
+            installed.version,
+            pkg.version,
+            pkg_name
+        );
+    }
+    let archive_path = download_pkg_with_cache(&pkg)?;
+    apply_archive_meta(&pkg, &archive_path)?;
+    Ok(archive_path)
+}
+
+fn open_archive(archive_path: &Path) -> Result<Archive<GzDecoder<File>>> {
+    Ok(Archive::new(GzDecoder::new(File::open(archive_path)?)))
+}
+
+/// Absolute package path for an archive entry (`./usr/bin/foo` -> `/usr/bin/foo`).
+pub fn package_path(entry_path: &Path) -> Option<String> {
+    let parts: Vec<_> = entry_path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(format!("/{}", parts.join("/")))
+    }
+}
+
+/// Where a package keeps its metadata inside the archive; it is never extracted.
+pub const META_PATH: &str = "/meta.toml";
+
+fn list_archive(archive_path: &Path) -> Result<Vec<String>> {
+    let mut archive = open_archive(archive_path)?;
+    let mut paths = Vec::new();
+    for entry in archive.entries()? {
+        if let Some(path) = package_path(&entry?.path()?)
+            && path != META_PATH
+        {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// The package's `meta.toml`, if the archive has one.
+pub fn read_meta(archive_path: &Path) -> Result<Option<Meta>> {
+    let mut archive = open_archive(archive_path)?;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if package_path(&entry.path()?).as_deref() != Some(META_PATH) {
+            continue;
+        }
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        let meta = toml::from_str(&content)
+            .with_context(|| format!("Invalid meta.toml in {}", archive_path.display()))?;
+        return Ok(Some(meta));
+    }
+    Ok(None)
+}
+
+/// Refuse the archive of `pkg_name` if its checksum differs from the `expected` one
+/// its index entry publishes.
+fn check_checksum(pkg_name: &str, expected: Option<&str>, archive_path: &Path) -> Result<()> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let actual = db::sha256_file(archive_path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        fs::remove_file(archive_path).ok();
+        bail!(
+            "Checksum mismatch for {} (index says {}, archive is {}); try again",
+            pkg_name,
+            expected,
+            actual
+        );
+    }
+    Ok(())
+}
+
+/// Refuse an archive whose metadata names a different package than the index entry,
+/// which points to a swapped or mislabeled upload.
+fn check_meta(pkg: &PackageInfo, meta: &Meta) -> Result<()> {
+    let sorted = |deps: &[String]| {
+        let mut deps = deps.to_vec();
+        deps.sort();
+        deps.join(", ")
+    };
+    let fields = [
+        ("name", pkg.name.clone(), meta.name.clone()),
+        ("version", pkg.version.clone(), meta.version.clone()),
+        ("arch", pkg.arch.clone(), meta.arch.clone()),
+        ("os", pkg.os.clone(), meta.os.clone()),
+        ("deps", sorted(&pkg.deps), sorted(&meta.deps)),
+    ];
+
+    let mismatches: Vec<String> = fields
+        .iter()
+        .filter(|(_, index, archive)| index != archive)
+        .map(|(field, index, archive)| {
+            format!("{}: index says '{}', archive says '{}'", field, index, archive)
+        })
+        .collect();
+    if !mismatches.is_empty() {
+        bail!(
+            "Archive of {} does not match its repository entry ({}); refusing to install it",
+            pkg.name,
+            mismatches.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// `pkg` as its archive describes it: the archive must agree with the index on which
+/// package it is, and its own descriptive fields, config and scripts take precedence.
+/// An archive without a meta.toml is refused if its entry publishes a checksum (as
+/// `anspm repo-build` always does), and only warned about otherwise.
+fn apply_archive_meta(pkg: &PackageInfo, archive_path: &Path) -> Result<PackageInfo> {
+    let mut pkg = pkg.clone();
+    let Some(meta) = read_meta(archive_path)? else {
+        if pkg.sha256.is_some() {
+            fs::remove_file(archive_path).ok();
+            bail!(
+                "Archive of {} has no meta.toml to check against its repository entry; refusing to install it",
+                pkg.name
+            );
+        }
+        print_error(&format!(
+            "Archive of {} has no meta.toml; cannot check it against its repository entry",
+            pkg.name
+        ));
+        return Ok(pkg);
+    };
+
+    if let Err(e) = check_meta(&pkg, &meta) {
+        // Do not keep serving the bad archive from the cache.
+        fs::remove_file(archive_path).ok();
+        return Err(e);
+    }
+    pkg.author = meta.author;
+    pkg.license = meta.license;
+    if let Some(description) = meta.description {
+        pkg.description = description;
+    }
+    if !meta.config.is_empty() {
+        pkg.config = meta.config;
+    }
+    if !meta.scripts.is_empty() {
+        pkg.scripts = meta.scripts;
+    }
+    Ok(pkg)
+}
+
+/// Suffix of the packaged version of a config file the user edited.
+const NEW_CONFIG_SUFFIX: &str = ".anspmnew";
+/// Suffix an edited config file is saved under when its package is removed.
+const SAVED_CONFIG_SUFFIX: &str = ".anspmsave";
+
+/// Whether `path` is a config file for a package declaring `config` (exact paths or `dir/**`).
+/// Packages that declare nothing get everything under `/etc`.
+fn is_config(config: &[String], path: &str) -> bool {
+    if config.is_empty() {
+        return path.starts_with("/etc/");
+    }
+    config.iter().any(|pattern| match pattern.strip_suffix("/**") {
+        Some(dir) => path.starts_with(&format!("{}/", dir)),
+        None => pattern == path,
+    })
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Config files about to be installed whose copy on disk differs from what `old` recorded
+/// (or that no version of the package installed), i.e. that an install must not overwrite.
+fn edited_configs(
+    config: &[String],
+    files: &[String],
+    old: Option<&InstalledPackage>,
+) -> Result<Vec<String>> {
+    let mut edited = Vec::new();
+    for path in files.iter().filter(|path| is_config(config, path)) {
+        let on_disk = root_path(path)?;
+        if !fs::symlink_metadata(&on_disk).is_ok_and(|meta| meta.is_file()) {
+            continue;
+        }
+        let recorded = old
+            .and_then(|old| old.files.iter().find(|f| &f.path == path))
+            .and_then(|f| f.sha256.as_deref());
+        if recorded != Some(db::sha256_file(&on_disk)?.as_str()) {
+            edited.push(path.clone());
+        }
+    }
+    Ok(edited)
+}
+
+/// Unpack every entry under the configured root, stopping between entries on Ctrl-C.
+/// Paths in `diverted` are unpacked next to the existing file as `<path>.anspmnew`.
+fn extract_archive(archive_path: &Path, diverted: &[String]) -> Result<()> {
+    let root = &settings()?.root;
+    let mut archive = open_archive(archive_path)?;
+    for entry in archive.entries()? {
+        if journal::interrupted() {
+            bail!("Interrupted");
+        }
+        let mut entry = entry?;
+        match package_path(&entry.path()?) {
+            Some(path) if path == META_PATH => {}
+            Some(path) if diverted.contains(&path) => {
+                entry.unpack(with_suffix(&root_path(&path)?, NEW_CONFIG_SUFFIX))?;
+            }
+            _ => {
+                entry.unpack_in(root)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copy a file, or a symlink as a link, from `from` to `to`, creating parent directories.
+fn copy_entry(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let result = if fs::symlink_metadata(from)?.file_type().is_symlink() {
+        fs::read_link(from).and_then(|target| std::os::unix::fs::symlink(target, to))
+    } else {
+        fs::copy(from, to).map(|_| ())
+    };
+    result.with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))
+}
+
+/// Copy aside the files and symlinks among `paths` before an install replaces or deletes
+/// them, and return which ones were copied.
+fn back_up(paths: &[String]) -> Result<Vec<String>> {
+    let dir = journal::backup_dir()?;
+    // Left over from a run that died before writing its journal.
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+
+    let mut backed_up = Vec::new();
+    for path in paths {
+        let on_disk = root_path(path)?;
+        if fs::symlink_metadata(&on_disk).is_ok_and(|meta| !meta.is_dir()) {
+            copy_entry(&on_disk, &dir.join(path.trim_start_matches('/')))?;
+            backed_up.push(path.clone());
+        }
+    }
+    Ok(backed_up)
+}
+
+/// Undo an unfinished install: delete what it created, put back the files it replaced
+/// and restore the database entry it may already have overwritten.
+fn rollback_install(journal: &Journal) -> Result<()> {
+    for path in &journal.diverted {
+        if let Ok(path) = root_path(path) {
+            fs::remove_file(with_suffix(&path, NEW_CONFIG_SUFFIX)).ok();
+        }
+    }
+
+    let mut paths: Vec<&str> = journal
+        .files
+        .iter()
+        .filter(|path| !journal.preexisting.contains(path))
+        .map(String::as_str)
+        .collect();
+    paths.sort_by(|a, b| b.cmp(a));
+
+    for path in paths {
+        let Ok(path) = root_path(path) else { continue };
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_dir() => fs::remove_dir(&path).ok(),
+            Ok(_) => fs::remove_file(&path).ok(),
+            Err(_) => None,
+        };
+    }
+
+    let backups = journal::backup_dir()?;
+    for path in &journal.backed_up {
+        let backup = backups.join(path.trim_start_matches('/'));
+        if fs::symlink_metadata(&backup).is_err() {
+            continue;
+        }
+        let on_disk = root_path(path)?;
+        if fs::symlink_metadata(&on_disk).is_ok_and(|meta| !meta.is_dir()) {
+            fs::remove_file(&on_disk)?;
+        }
+        copy_entry(&backup, &on_disk)?;
+    }
+
+    let mut db = db::load()?;
+    match &journal.previous {
+        Some(previous) => {
+            db.packages.insert(journal.package.clone(), previous.clone());
+        }
+        // A fresh install; journals of older anspm versions also lack `previous` on upgrade,
+        // so only drop an entry that is this install's.
+        None => {
+            if db.packages.get(&journal.package).is_none_or(|p| p.version != journal.version) {
+                return Ok(());
+            }
+            db.packages.remove(&journal.package);
+        }
+    }
+    db::save(&db)
+}
+
+/// Files of the installed `old` version that a version shipping `files` no longer has.
+/// Old entries may be unnormalized (`/./usr/bin/foo`), so paths are compared on disk.
+fn obsolete_files(old: &InstalledPackage, files: &[String]) -> Result<Vec<FileEntry>> {
+    let shipped = files
+        .iter()
+        .map(|path| root_path(path))
+        .collect::<Result<HashSet<_>>>()?;
+    let mut obsolete = Vec::new();
+    for file in &old.files {
+        let Some(path) = package_path(Path::new(&file.path)) else { continue };
+        if !shipped.contains(&root_path(&path)?) {
+            obsolete.push(FileEntry { path, ..file.clone() });
+        }
+    }
+    Ok(obsolete)
+}
+
+/// Capture the unpacked files and record the journal's pending entry in installed.db.
+fn finish_install(journal: &Journal) -> Result<()> {
+    let Some(entry) = &journal.entry else {
+        bail!("Journal for {} has no package entry", journal.package);
+    };
+
+    if journal.files.iter().all(|f| !root_path(f).is_ok_and(|p| p.exists())) {
+        return Err(anyhow::anyhow!("No package files were installed"));
     }
 
-    print_info(&format!("Downloading package: {}", url));
-    let pkg_data = reqwest::blocking::get(url)?
-    .bytes()?
-    .to_vec();
+    // Directories that were already there belong to the system (or another package),
+    // unless this package created them in an earlier version.
+    let db = db::load()?;
+    let owned_before = |path: &str| {
+        db.packages
+            .get(&journal.package)
+            .is_some_and(|old| old.files.iter().any(|f| f.path == path && !f.shared))
+    };
+
+    let mut files = Vec::with_capacity(journal.files.len());
+    for path in &journal.files {
+        let on_disk = root_path(path)?;
+        let mut file = if journal.diverted.contains(path) {
+            keep_edited_config(path, &on_disk)?
+        } else {
+            FileEntry::capture(path, &on_disk)?
+        };
+        file.shared = file.kind == Some(FileKind::Directory)
+            && journal.preexisting.contains(path)
+            && !owned_before(path);
+        file.config = file.kind == Some(FileKind::File) && is_config(&entry.meta.config, path);
+        files.push(file);
+    }
+
+    // On upgrade, drop what the old version shipped and the new one does not, before the
+    // old file list is replaced; finishing again after a crash simply finds less to remove.
+    if let Some(old) = db.packages.get(&journal.package) {
+        let obsolete = obsolete_files(old, &journal.files)?;
+        if !obsolete.is_empty() {
+            print_info(&format!(
+                "Removing {} file(s) no longer shipped by {}",
+                obsolete.len(),
+                journal.package
+            ));
+            remove_files(&journal.package, &obsolete, ConfigFiles::Save)?;
+        }
+    }
+
+    triggers::touch(journal.files.iter().map(String::as_str));
+
+    let mut entry = entry.clone();
+    entry.files = files;
+    entry.install_date = db::now();
+    update_package_db(&journal.package, entry)
+}
+
+/// Record a diverted config file as packaged, so it still counts as edited next time,
+/// and drop the `.anspmnew` copy if it turned out identical to the user's file.
+fn keep_edited_config(path: &str, on_disk: &Path) -> Result<FileEntry> {
+    let new_path = with_suffix(on_disk, NEW_CONFIG_SUFFIX);
+    if !new_path.exists() {
+        return FileEntry::capture(path, on_disk);
+    }
+
+    let file = FileEntry::capture(path, &new_path)?;
+    if file.sha256.as_deref() == Some(db::sha256_file(on_disk)?.as_str()) {
+        fs::remove_file(&new_path)?;
+    } else {
+        print_info(&format!(
+            "Kept your edited {}; the packaged version is in {}",
+            path,
+            new_path.display()
+        ));
+    }
+    Ok(file)
+}
+
+/// Whether `install` was given a package file or URL rather than a package name.
+pub fn is_package_file(arg: &str) -> bool {
+    arg.starts_with("http://") || arg.starts_with("https://") || arg.ends_with(".pkg") || arg.contains('/')
+}
+
+/// Install a `.pkg` from a path or URL. The archive's own `meta.toml` describes it, a
+/// detached `<source>.asc` signature is checked if there is one (and must be, with
+/// `verify_sig`), and dependencies come from the configured repositories.
+pub fn install_file(source: &str, verify_sig: bool) -> Result<()> {
+    print_info(&format!("Installing package file: {}", source));
 
-    fs::write(&cached_path, &pkg_data)?;
-    print_info(&format!("Cached package at: {}", cached_path.display()));
+    let (source, content, signature) = if source.starts_with("http://") || source.starts_with("https://") {
+        let signature = http::get_bytes(&format!("{}.asc", source)).ok();
+        (source.to_string(), http::get_bytes(source)?, signature)
+    } else {
+        let path = fs::canonicalize(source).with_context(|| format!("Failed to read {}", source))?;
+        let content = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut signature_path = path.clone().into_os_string();
+        signature_path.push(".asc");
+        let signature = fs::read(signature_path).ok();
+        (path.to_string_lossy().into_owned(), content, signature)
+    };
+    repo::verify_package_file(&source, &content, signature.as_deref(), verify_sig)?;
+
+    // Stage the archive in the cache, then name it like a download once its metadata is known.
+    let file_name = source.rsplit('/').next().unwrap_or("package.pkg");
+    let staged = get_cache_dir()?.join(format!("local-{}", file_name));
+    db::write_atomic(&staged, &content)?;
+    let Some(meta) = read_meta(&staged)? else {
+        fs::remove_file(&staged).ok();
+        bail!("{} has no meta.toml; only packages built with `anspm build` can be installed from a file", source);
+    };
+    let archive_path = staged.with_file_name(format!("{}-{}", meta.name, file_name));
+    fs::rename(&staged, &archive_path)?;
+
+    let pkg = PackageInfo {
+        name: meta.name,
+        version: meta.version,
+        description: meta.description.unwrap_or_else(|| "No description".to_string()),
+        url: source.clone(),
+        os: meta.os,
+        arch: meta.arch,
+        deps: meta.deps,
+        author: meta.author,
+        license: meta.license,
+        size: Some(content.len() as u64),
+        sha256: None,
+        config: meta.config,
+        scripts: meta.scripts,
+        base_url: String::new(),
+        repo: String::new(),
+    };
+    check_os(&pkg)?;
+    check_hold(&pkg)?;
+
+    let db = db::load()?;
+    if let Some(installed) = db.packages.get(&pkg.name)
+        && installed.version == pkg.version
+    {
+        print_info(&format!("Package {} v{} is already installed.", pkg.name, pkg.version));
+        return Ok(());
+    }
+
+    for dep in resolve_missing_deps(&pkg, &db)? {
+        print_info(&format!("Installing dependency {} of {}", dep.name, pkg.name));
+        install_package(&dep, InstallReason::Dependency)?;
+    }
 
-    Ok(pkg_data)
+    let reason = db.packages.get(&pkg.name).map_or(InstallReason::Explicit, |p| p.reason);
+    install_archive(&pkg, reason, &archive_path, Some(&source))
 }
 
 pub fn install(pkg_name: &str, check: bool) -> Result<()> {
     print_info(&format!("Installing package: {}", pkg_name));
 
     let pkg = repo::find_package(pkg_name)?;
-    if check {
-        if let Ok(db) = read_tracking_file() {
-            if let Some(installed_pkg) = db.get(pkg_name) {
-                let installed_ver = installed_pkg["version"].as_str().unwrap_or("");
-
-                if installed_ver == pkg.version {
-                    print_info(&format!(
-                        "Package {} v{} is already installed. Use anspm reinstall {} to reinstall.",
-                        pkg_name, pkg.version, pkg_name
-                    ));
-                    return Ok(());
-                } else if *installed_ver > *pkg.version {
-                    print_info(&format!(
-                        "Newer version ({}) is already installed. Downgrading to {} requires anspm reinstall {}.",
-                        installed_ver, pkg.version, pkg_name
-                    ));
-                    return Ok(());
-                } else {
-                    print_info(&format!(
-                        "Package {} is installed ({}). New version {} available.\nRun `anspm update {}` to update.",
-                        pkg_name, installed_ver, pkg.version, pkg_name
-                    ));
-                    return Ok(());
-                }
+    let mut db = db::load()?;
+    if check && let Some(installed_pkg) = db.packages.get_mut(pkg_name) {
+        // Asking for a package by name makes it explicit, even if it only came in as a dependency.
+        if installed_pkg.reason == InstallReason::Dependency {
+            installed_pkg.reason = InstallReason::Explicit;
+            db::save(&db)?;
+            print_info(&format!("Marked {} as explicitly installed", pkg_name));
+        }
+
+        let installed_ver = db.packages[pkg_name].version.as_str();
+
+        if installed_ver == pkg.version {
+            print_info(&format!(
+                "Package {} v{} is already installed. Use anspm reinstall {} to reinstall.",
+                pkg_name, pkg.version, pkg_name
+            ));
+            return Ok(());
+        } else if version::compare(installed_ver, &pkg.version) == Ordering::Greater {
+            print_info(&format!(
+                "Newer version ({}) is already installed. Downgrading to {} requires anspm reinstall {}.",
+                installed_ver, pkg.version, pkg_name
+            ));
+            return Ok(());
+        } else {
+            print_info(&format!(
+                "Package {} is installed ({}). New version {} available.\nRun `anspm update {}` to update.",
+                pkg_name, installed_ver, pkg.version, pkg_name
+            ));
+            return Ok(());
+        }
+    }
+
+    if !db.packages.contains_key(pkg_name) {
+        check_hold(&pkg)?;
+    }
+
+    // An upgrade keeps whatever reason the package was first installed for.
+    let reason = db.packages.get(pkg_name).map_or(InstallReason::Explicit, |p| p.reason);
+    install_with_deps(&pkg, reason)
+}
+
+/// Install `pkg` after whatever it depends on that is not installed yet.
+fn install_with_deps(pkg: &PackageInfo, reason: InstallReason) -> Result<()> {
+    let db = db::load()?;
+    for dep in resolve_missing_deps(pkg, &db)? {
+        print_info(&format!("Installing dependency {} of {}", dep.name, pkg.name));
+        install_package(&dep, InstallReason::Dependency)?;
+    }
+    install_package(pkg, reason)
+}
+
+/// The hold configured for `pkg_name`, if any.
+fn held(pkg_name: &str) -> Result<Option<Constraint>> {
+    settings()?
+        .holds
+        .get(pkg_name)
+        .map(|raw| {
+            Constraint::parse(raw).with_context(|| format!("Invalid hold for {}", pkg_name))
+        })
+        .transpose()
+}
+
+fn check_hold(pkg: &PackageInfo) -> Result<()> {
+    if let Some(hold) = held(&pkg.name)?
+        && !hold.matches(&pkg.version)
+    {
+        bail!(
+            "{} is held at {}, but the repository offers {}",
+            pkg.name,
+            hold,
+            pkg.version
+        );
+    }
+    Ok(())
+}
+
+/// Hold `spec` (`name` or `name@constraint`); a bare name holds the installed version.
+pub fn hold(spec: &str, system: bool) -> Result<()> {
+    let (name, constraint) = match spec.split_once('@') {
+        Some((name, constraint)) => (name, constraint.to_string()),
+        None => {
+            let db = db::load()?;
+            let Some(installed) = db.packages.get(spec) else {
+                bail!("{} is not installed; give a version, e.g. {}@>=1.0", spec, spec);
+            };
+            (spec, format!("={}", installed.version))
+        }
+    };
+    Constraint::parse(&constraint)?;
+
+    let path = edit_settings_file(system, |file| {
+        let table = file
+            .as_table_mut()
+            .ok_or_else(|| anyhow::anyhow!("anspm.toml is not a table"))?;
+        let holds = table
+            .entry("holds")
+            .or_insert_with(|| toml::Value::Table(toml::map::Map::new()))
+            .as_table_mut()
+            .ok_or_else(|| anyhow::anyhow!("'holds' is not a table"))?;
+        holds.insert(name.to_string(), toml::Value::String(constraint.clone()));
+        Ok(())
+    })?;
+
+    print_success(&format!("Holding {} at {} ({})", name, constraint, path.display()));
+    Ok(())
+}
+
+pub fn unhold(pkg_name: &str, system: bool) -> Result<()> {
+    let mut found = false;
+    let path = edit_settings_file(system, |file| {
+        found = file
+            .get_mut("holds")
+            .and_then(toml::Value::as_table_mut)
+            .and_then(|holds| holds.remove(pkg_name))
+            .is_some();
+        Ok(())
+    })?;
+
+    if !found {
+        bail!("{} is not held in {}", pkg_name, path.display());
+    }
+    print_success(&format!("Released hold on {}", pkg_name));
+    Ok(())
+}
+
+/// Dependencies of `pkg` (transitively) that are not installed yet, in install order.
+fn resolve_missing_deps(pkg: &PackageInfo, db: &db::Database) -> Result<Vec<PackageInfo>> {
+    if pkg.deps.iter().all(|dep| db.packages.contains_key(dep)) {
+        return Ok(Vec::new());
+    }
+
+    let available = repo::fetch_all_packages()?;
+    let mut order = Vec::new();
+    let mut seen = HashSet::from([pkg.name.clone()]);
+
+    fn visit(
+        pkg: &PackageInfo,
+        available: &HashMap<String, PackageInfo>,
+        db: &db::Database,
+        seen: &mut HashSet<String>,
+        order: &mut Vec<PackageInfo>,
+    ) -> Result<()> {
+        for dep in &pkg.deps {
+            if db.packages.contains_key(dep) || !seen.insert(dep.clone()) {
+                continue;
             }
+            let Some(dep_pkg) = available.get(dep) else {
+                bail!("{} depends on {}, which is not available in any repository", pkg.name, dep);
+            };
+            check_hold(dep_pkg)?;
+            visit(dep_pkg, available, db, seen, order)?;
+            order.push(dep_pkg.clone());
         }
+        Ok(())
     }
 
+    visit(pkg, &available, db, &mut seen, &mut order)?;
+    Ok(order)
+}
+
+fn check_os(pkg: &PackageInfo) -> Result<()> {
     if pkg.os != "all" && pkg.os != std::env::consts::OS {
         return Err(anyhow::anyhow!(
             "Package '{}' is for {} (your OS is {})",
-            pkg_name,
+            pkg.name,
             pkg.os,
             std::env::consts::OS
         ));
     }
+    Ok(())
+}
 
-    print_info(&format!("Starting use package from: {}", pkg.url));
+/// Download, unpack and record one package, with no dependency handling.
+fn install_package(pkg: &PackageInfo, reason: InstallReason) -> Result<()> {
+    check_os(pkg)?;
 
-    let full_url = Url::parse(&pkg.base_url)
-        .unwrap()
-        .join(&pkg.url)
-        .unwrap();
-    let pkg_data = download_pkg_with_cache(pkg_name, full_url.as_str())?;
+    print_info(&format!("Starting use package from: {}", pkg.url));
 
-    let temp_dir = tempfile::tempdir()?;
-    let pkg_path = temp_dir.path().join(format!("{}.pkg", pkg_name));
-    fs::write(&pkg_path, &pkg_data)?;
+    let archive_path = download_pkg_with_cache(pkg)?;
+    install_archive(pkg, reason, &archive_path, None)
+}
 
-    print_info("Installing files to system...");
+/// Unpack and record a package from an archive already in the cache. `source` is the
+/// file or URL it came from when it was not installed from a repository.
+fn install_archive(
+    pkg: &PackageInfo,
+    reason: InstallReason,
+    archive_path: &Path,
+    source: Option<&str>,
+) -> Result<()> {
+    let pkg_name = pkg.name.as_str();
+    let pkg = &apply_archive_meta(pkg, archive_path)?;
 
-    let pkg_file = File::open(&pkg_path)?;
-    let decompressor = GzDecoder::new(pkg_file);
-    let mut archive = Archive::new(decompressor);
+    let db = db::load()?;
+    let old = db.packages.get(pkg_name);
+    let mut hooks = scripts::Package {
+        name: pkg_name,
+        version: &pkg.version,
+        old_version: old.map(|old| old.version.as_str()),
+        scripts: &pkg.scripts,
+        policy: scripts::policy(Some(&pkg.repo))?,
+        files: old.map_or(&[], |old| &old.files),
+    };
+    scripts::check_allowed(&hooks)?;
 
-    archive.unpack("/")?;
+    let files = list_archive(archive_path)?;
+    let preexisting: Vec<String> = files
+        .iter()
+        .filter(|path| root_path(path).is_ok_and(|p| fs::symlink_metadata(p).is_ok()))
+        .cloned()
+        .collect();
+    let diverted = edited_configs(&pkg.config, &files, old)?;
 
-    let pkg_file = File::open(&pkg_path)?;
-    let decompressor = GzDecoder::new(pkg_file);
-    let mut archive = Archive::new(decompressor);
+    let (pre_hook, post_hook) = match old {
+        Some(_) => (Hook::PreUpgrade, Hook::PostUpgrade),
+        None => (Hook::PreInstall, Hook::PostInstall),
+    };
+    scripts::run(&hooks, pre_hook)?;
 
-    let installed_files: Vec<String> = archive.entries()?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| {
-            let path_cow = entry.path().unwrap_or_else(|_| std::path::PathBuf::new().into());
-            format!("/{}", path_cow.display())
-        })
+    // What gets overwritten, or removed as obsolete, is saved for a rollback.
+    let mut replaced: Vec<String> = preexisting
+        .iter()
+        .filter(|path| !diverted.contains(path))
+        .cloned()
         .collect();
+    if let Some(old) = old {
+        replaced.extend(obsolete_files(old, &files)?.into_iter().map(|f| f.path));
+    }
+    let backed_up = back_up(&replaced)?;
 
-    if installed_files.iter().all(|f| !Path::new(f).exists()) {
-        return Err(anyhow::anyhow!("No package files were installed"));
+    let journal = Journal {
+        operation: Operation::Install,
+        package: pkg_name.to_string(),
+        version: pkg.version.clone(),
+        started: db::now(),
+        files,
+        preexisting,
+        diverted,
+        purge: false,
+        backed_up,
+        previous: old.cloned(),
+        archive: Some(archive_path.to_path_buf()),
+        sha256: pkg.sha256.clone(),
+        entry: Some(InstalledPackage {
+            version: pkg.version.clone(),
+            repo: if source.is_some() { None } else { Some(pkg.repo.clone()) },
+            reason,
+            install_date: 0,
+            files: Vec::new(),
+            meta: pkg.into(),
+            archive: archive_path.file_name().map(|n| n.to_string_lossy().into_owned()),
+            source: source.map(String::from),
+        }),
+    };
+    let interrupts = journal::defer_interrupts()?;
+    journal::begin(&journal)?;
+
+    print_info("Installing files to system...");
+
+    if let Err(e) = extract_archive(archive_path, &journal.diverted) {
+        print_error(&format!("Install of {} failed, rolling back: {}", pkg_name, e));
+        // Should the rollback fail too, the journal stays for `anspm recover`.
+        rollback_install(&journal)?;
+        journal::clear()?;
+        return Err(e);
     }
 
-    update_package_db(pkg_name, &pkg, &installed_files)?;
+    finish_install(&journal)?;
+    journal::clear()?;
+    drop(interrupts);
+
+    let installed = db::load()?;
+    hooks.files = installed.packages.get(pkg_name).map_or(&[], |p| &p.files);
+    scripts::run(&hooks, post_hook)?;
 
     print_success(&format!(
         "Package {} v{} installed successfully!",
@@ -152,64 +860,361 @@ pub fn clean_cache() -> Result<()> {
     Ok(())
 }
 
-pub fn remove(pkg_name: &str) -> Result<()> {
-    print_info(&format!("Removing package: {}", pkg_name));
+/// What removing a package does with config files the user edited.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConfigFiles {
+    /// Rename them to `<path>.anspmsave`.
+    Save,
+    /// Leave them in place for the following install to keep.
+    Keep,
+    /// Delete them like any other file.
+    Purge,
+}
+
+/// Delete a package's files, deepest first. Symlinks are removed as links, never followed;
+/// paths another package also lists are left alone; directories are only removed when the
+/// package created them and they are empty.
+fn remove_files(pkg_name: &str, files: &[FileEntry], configs: ConfigFiles) -> Result<()> {
+    let index = db::load_index()?;
+    triggers::touch(files.iter().map(|f| f.path.as_str()));
+    let mut files: Vec<&FileEntry> = files.iter().collect();
+    files.sort_by(|a, b| b.path.cmp(&a.path));
+
+    for file in files {
+        let listed_by_others = index
+            .get(&file.path)
+            .is_some_and(|owners| owners.iter().any(|owner| owner != pkg_name));
+        if listed_by_others {
+            continue;
+        }
+
+        let path = root_path(&file.path)?;
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if file.config {
+            fs::remove_file(with_suffix(&path, NEW_CONFIG_SUFFIX)).ok();
+        }
+
+        if metadata.is_dir() {
+            if !file.shared {
+                // Fails, as intended, while something else still lives in it.
+                fs::remove_dir(&path).ok();
+            }
+        } else if file.config
+            && configs != ConfigFiles::Purge
+            && metadata.is_file()
+            && file.sha256.as_deref() != Some(db::sha256_file(&path)?.as_str())
+        {
+            if configs == ConfigFiles::Save {
+                let saved = with_suffix(&path, SAVED_CONFIG_SUFFIX);
+                fs::rename(&path, &saved)
+                    .with_context(|| format!("Failed to save {}", path.display()))?;
+                print_info(&format!("Saved your edited {} as {}", file.path, saved.display()));
+            }
+        } else {
+            fs::remove_file(&path).ok();
+        }
+    }
+    Ok(())
+}
+
+/// Installed packages whose stored deps list `pkg_name`.
+fn dependents(db: &db::Database, pkg_name: &str) -> Vec<String> {
+    db.packages
+        .iter()
+        .filter(|(name, pkg)| *name != pkg_name && pkg.meta.deps.iter().any(|d| d == pkg_name))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
 
-    let mut db = read_tracking_file()?;
-    if !db.as_object().unwrap().contains_key(pkg_name) {
+/// `pkg_name` and everything depending on it, transitively, dependents first.
+fn removal_order(db: &db::Database, pkg_name: &str) -> Vec<String> {
+    fn visit(db: &db::Database, name: &str, seen: &mut HashSet<String>, order: &mut Vec<String>) {
+        if !seen.insert(name.to_string()) {
+            return;
+        }
+        for dependent in dependents(db, name) {
+            visit(db, &dependent, seen, order);
+        }
+        order.push(name.to_string());
+    }
+
+    let mut order = Vec::new();
+    visit(db, pkg_name, &mut HashSet::new(), &mut order);
+    order
+}
+
+/// Every chain of installed packages leading from an explicitly installed package to `pkg_name`.
+fn dependency_chains(db: &db::Database, pkg_name: &str) -> Vec<Vec<String>> {
+    fn walk(db: &db::Database, path: &mut Vec<String>, chains: &mut Vec<Vec<String>>) {
+        let head = path[0].clone();
+        for dependent in dependents(db, &head) {
+            if path.contains(&dependent) {
+                continue;
+            }
+            path.insert(0, dependent.clone());
+            if db.packages[&dependent].reason == InstallReason::Explicit {
+                chains.push(path.clone());
+            } else {
+                walk(db, path, chains);
+            }
+            path.remove(0);
+        }
+    }
+
+    let mut chains = Vec::new();
+    walk(db, &mut vec![pkg_name.to_string()], &mut chains);
+    chains.sort();
+    chains
+}
+
+pub fn why(pkg_name: &str) -> Result<()> {
+    let db = db::load()?;
+    let Some(pkg) = db.packages.get(pkg_name) else {
+        bail!("Package {} is not installed", pkg_name);
+    };
+
+    let chains = dependency_chains(&db, pkg_name);
+    let explicit = pkg.reason == InstallReason::Explicit;
+
+    if explicit {
+        println!("{} was explicitly installed", pkg_name.green().bold());
+    }
+    if chains.is_empty() {
+        if !explicit {
+            println!(
+                "{} was installed as a dependency, but nothing needs it anymore (see `anspm autoremove`)",
+                pkg_name.green().bold()
+            );
+        }
+        return Ok(());
+    }
+
+    println!(
+        "{} is {}required by:",
+        pkg_name.green().bold(),
+        if explicit { "also " } else { "" }
+    );
+    for chain in chains {
+        println!("  {}", chain.join(" -> "));
+    }
+    Ok(())
+}
+
+/// Remove a package, refusing while other installed packages depend on it unless
+/// `cascade` (remove them too) or `force` (leave them broken) is given.
+/// Edited config files are saved as `.anspmsave` unless `purge` is set.
+pub fn remove(pkg_name: &str, cascade: bool, force: bool, purge: bool) -> Result<()> {
+    let configs = if purge { ConfigFiles::Purge } else { ConfigFiles::Save };
+    let db = db::load()?;
+    if !db.packages.contains_key(pkg_name) {
         print_error(&format!("Package {} not found!", pkg_name));
         return Ok(());
     }
 
-    if let Some(files) = db[pkg_name]["files"].as_array() {
-        let mut paths: Vec<&str> = files.iter().filter_map(|v| v.as_str()).collect();
-        paths.sort_by(|a, b| b.cmp(a));
+    let dependents = dependents(&db, pkg_name);
+    if dependents.is_empty() || force {
+        if !dependents.is_empty() {
+            print_error(&format!(
+                "Removing {} anyway; these packages will be broken: {}",
+                pkg_name,
+                dependents.join(", ")
+            ));
+        }
+        return remove_package(pkg_name, configs);
+    }
 
-        for path in paths {
-            let path = Path::new(path);
-            if path.exists() {
-                if path.is_file() {
-                    fs::remove_file(path).ok();
-                } else if path.is_dir() {
-                    fs::remove_dir(path).ok();
-                }
-            }
+    if !cascade {
+        print_error(&format!("{} is required by:", pkg_name));
+        for dependent in &dependents {
+            println!("  {}", dependent.yellow());
         }
+        bail!(
+            "Refusing to remove {}; use --cascade to remove its dependents too or --force to break them",
+            pkg_name
+        );
     }
 
-    db.as_object_mut().unwrap().remove(pkg_name);
-    write_tracking_file(&db)?;
+    let order = removal_order(&db, pkg_name);
+    print_info(&format!("Removing {} and its dependents: {}", pkg_name, order.join(", ")));
+    for name in order {
+        if journal::interrupted() {
+            bail!("Interrupted");
+        }
+        remove_package(&name, configs)?;
+    }
+    Ok(())
+}
+
+fn remove_package(pkg_name: &str, configs: ConfigFiles) -> Result<()> {
+    print_info(&format!("Removing package: {}", pkg_name));
+
+    let mut db = db::load()?;
+    let Some(installed) = db.packages.remove(pkg_name) else {
+        print_error(&format!("Package {} not found!", pkg_name));
+        return Ok(());
+    };
+
+    let hooks = scripts::Package {
+        name: pkg_name,
+        version: &installed.version,
+        old_version: None,
+        scripts: &installed.meta.scripts,
+        policy: scripts::policy(installed.repo.as_deref())?,
+        files: &installed.files,
+    };
+    scripts::run(&hooks, Hook::PreRemove)?;
+
+    let journal = Journal {
+        operation: Operation::Remove,
+        package: pkg_name.to_string(),
+        version: installed.version.clone(),
+        started: db::now(),
+        files: installed.files.iter().map(|f| f.path.clone()).collect(),
+        preexisting: Vec::new(),
+        diverted: Vec::new(),
+        purge: configs == ConfigFiles::Purge,
+        backed_up: Vec::new(),
+        previous: None,
+        archive: None,
+        sha256: None,
+        entry: Some(installed.clone()),
+    };
+    // Removal is always carried through once started; a Ctrl-C only stops later steps.
+    let interrupts = journal::defer_interrupts()?;
+    journal::begin(&journal)?;
+    remove_files(pkg_name, &installed.files, configs)?;
+
+    db::save(&db)?;
+    journal::clear()?;
+    drop(interrupts);
+
+    scripts::run(&hooks, Hook::PostRemove)?;
 
     print_success(&format!("Package {} removed successfully!", pkg_name));
     Ok(())
 }
 
+/// Finish (`forward = true`) or undo the operation recorded by an interrupted run.
+pub fn recover(forward: bool) -> Result<()> {
+    let Some(journal) = journal::pending()? else {
+        print_info("No interrupted transaction found.");
+        return Ok(());
+    };
+    let _interrupts = journal::defer_interrupts()?;
+
+    match (journal.operation, forward) {
+        (Operation::Install, true) => {
+            let Some(archive) = journal.archive.as_deref().filter(|a| a.exists()) else {
+                bail!(
+                    "Cached archive for {} is gone; roll back with `anspm recover --back` and install again",
+                    journal.package
+                );
+            };
+            check_checksum(&journal.package, journal.sha256.as_deref(), archive)?;
+            print_info(&format!("Completing install of {} {}", journal.package, journal.version));
+            extract_archive(archive, &journal.diverted)?;
+            finish_install(&journal)?;
+        }
+        (Operation::Install, false) => {
+            print_info(&format!("Rolling back install of {} {}", journal.package, journal.version));
+            rollback_install(&journal)?;
+        }
+        (Operation::Remove, true) => {
+            print_info(&format!("Completing removal of {} {}", journal.package, journal.version));
+            let files = match &journal.entry {
+                Some(entry) => entry.files.clone(),
+                None => journal.files.iter().map(|path| FileEntry::bare(path)).collect(),
+            };
+            let configs = if journal.purge { ConfigFiles::Purge } else { ConfigFiles::Save };
+            remove_files(&journal.package, &files, configs)?;
+            let mut db = db::load()?;
+            db.packages.remove(&journal.package);
+            db::save(&db)?;
+        }
+        (Operation::Remove, false) => {
+            print_info(&format!(
+                "Keeping {} registered; files already deleted can be restored with `anspm reinstall {}`",
+                journal.package, journal.package
+            ));
+        }
+    }
+
+    journal::clear()?;
+    print_success("Recovered interrupted transaction");
+    Ok(())
+}
+
+/// Called before any mutating command: resolve a transaction left by a killed run,
+/// asking on a terminal and refusing to continue otherwise.
+pub fn resume_interrupted() -> Result<()> {
+    let Some(journal) = journal::pending()? else {
+        return Ok(());
+    };
+
+    let op = match journal.operation {
+        Operation::Install => "install",
+        Operation::Remove => "removal",
+    };
+    print_error(&format!(
+        "A previous {} of {} {} was interrupted",
+        op, journal.package, journal.version
+    ));
+
+    if !std::io::stdin().is_terminal() {
+        bail!("Run `anspm recover --forward` or `anspm recover --back` to resolve it first");
+    }
+
+    loop {
+        print!("Roll [f]orward, roll [b]ack or [a]bort? ");
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        match answer.trim() {
+            "f" | "F" => return recover(true),
+            "b" | "B" => return recover(false),
+            "a" | "A" | "" => bail!("Aborted; the interrupted transaction is still pending"),
+            _ => continue,
+        }
+    }
+}
+
 pub fn update(only: Option<&str>) -> Result<()> {
-    let db = read_tracking_file()?;
-    let _repos = repo::load_repos_config()?;
-
-    if let Some(packages) = db.as_object() {
-        for (pkg_name, pkg_info) in packages {
-            if let Some(only_pkg) = only {
-                if pkg_name != only_pkg {
-                    continue;
-                }
-            }
+    let db = db::load()?;
 
-            if let Some(installed_version) = pkg_info["version"].as_str() {
-                if let Ok(latest_pkg) = repo::find_package(pkg_name) {
-                    if *latest_pkg.version > *installed_version {
-                        println!(
-                            "Update available for {}: {} -> {}.",
-                            pkg_name.green().bold(),
-                                installed_version,
-                                latest_pkg.version
-                        );
-                        println!("Updating {} to {}...", pkg_name, latest_pkg.version);
-                        install(pkg_name, false)?;
-                    }
-                }
+    for (pkg_name, pkg_info) in &db.packages {
+        if let Some(only_pkg) = only
+            && pkg_name != only_pkg
+        {
+            continue;
+        }
+
+        if journal::interrupted() {
+            bail!("Interrupted");
+        }
+
+        let installed_version = pkg_info.version.as_str();
+        if let Ok(latest_pkg) = repo::find_package(pkg_name)
+            && version::compare(&latest_pkg.version, installed_version) == Ordering::Greater
+        {
+            println!(
+                "Update available for {}: {} -> {}.",
+                pkg_name.green().bold(),
+                    installed_version,
+                    latest_pkg.version
+            );
+            if let Some(hold) = held(pkg_name)?
+                && !hold.matches(&latest_pkg.version)
+            {
+                print_info(&format!(
+                    "Skipping {}: held at {} (run `anspm unhold {}` to allow the update)",
+                    pkg_name, hold, pkg_name
+                ));
+                continue;
             }
+            println!("Updating {} to {}...", pkg_name, latest_pkg.version);
+            install(pkg_name, false)?;
         }
     }
 
@@ -217,15 +1222,203 @@ pub fn update(only: Option<&str>) -> Result<()> {
 }
 
 pub fn reinstall(pkg_name: &str) -> Result<()> {
-    remove(pkg_name)?;
-    install(pkg_name, false)
+    // Check, fetch and verify first so a hold or a bad download does not leave the
+    // package removed; the install below then uses that very archive.
+    let pkg = repo::find_package(pkg_name)?;
+    check_os(&pkg)?;
+    check_hold(&pkg)?;
+    let archive_path = download_pkg_with_cache(&pkg)?;
+    let pkg = apply_archive_meta(&pkg, &archive_path)?;
+
+    let db = db::load()?;
+    for dep in resolve_missing_deps(&pkg, &db)? {
+        print_info(&format!("Installing dependency {} of {}", dep.name, pkg.name));
+        install_package(&dep, InstallReason::Dependency)?;
+    }
+
+    // Removal drops the DB entry, so remember why the package was installed first.
+    let reason = db.packages.get(pkg_name).map_or(InstallReason::Explicit, |p| p.reason);
+    remove_package(pkg_name, ConfigFiles::Keep)?;
+    install_archive(&pkg, reason, &archive_path, None)
+}
+
+/// Dependency-installed packages that no explicitly installed package needs,
+/// directly or through other dependencies.
+fn orphans(db: &db::Database) -> Vec<String> {
+    let mut needed = HashSet::new();
+    let mut stack: Vec<&str> = db
+        .packages
+        .iter()
+        .filter(|(_, pkg)| pkg.reason == InstallReason::Explicit)
+        .map(|(name, _)| name.as_str())
+        .collect();
+
+    while let Some(name) = stack.pop() {
+        if !needed.insert(name) {
+            continue;
+        }
+        if let Some(pkg) = db.packages.get(name) {
+            stack.extend(pkg.meta.deps.iter().map(String::as_str));
+        }
+    }
+
+    db.packages
+        .keys()
+        .filter(|name| !needed.contains(name.as_str()))
+        .cloned()
+        .collect()
+}
+
+pub fn mark(pkg_names: &[String], reason: InstallReason) -> Result<()> {
+    let mut db = db::load()?;
+    for name in pkg_names {
+        let Some(pkg) = db.packages.get_mut(name) else {
+            bail!("Package {} not found", name);
+        };
+        pkg.reason = reason;
+    }
+    db::save(&db)?;
+
+    let label = match reason {
+        InstallReason::Explicit => "explicitly installed",
+        InstallReason::Dependency => "installed as a dependency",
+    };
+    print_success(&format!("Marked {} as {}", pkg_names.join(", "), label));
+    Ok(())
+}
+
+/// Remove every orphan, dependents before the packages they depend on.
+pub fn autoremove() -> Result<()> {
+    let db = db::load()?;
+    let mut remaining = orphans(&db);
+    if remaining.is_empty() {
+        print_info("No orphaned packages to remove.");
+        return Ok(());
+    }
+
+    print_info(&format!("Removing orphaned packages: {}", remaining.join(", ")));
+    while !remaining.is_empty() {
+        let is_needed = |name: &String| {
+            remaining
+                .iter()
+                .any(|other| other != name && db.packages[other].meta.deps.contains(name))
+        };
+        // A dependency cycle leaves nothing unneeded; just take the rest in order then.
+        let next = remaining
+            .iter()
+            .position(|name| !is_needed(name))
+            .unwrap_or(0);
+        let name = remaining.remove(next);
+
+        if journal::interrupted() {
+            bail!("Interrupted");
+        }
+        remove_package(&name, ConfigFiles::Save)?;
+    }
+    Ok(())
+}
+
+/// Restore files that `verify` reports as damaged by re-extracting just those entries
+/// from the package archive. Edited config files are left as they are.
+pub fn repair(pkg_name: &str) -> Result<()> {
+    let mut db = db::load()?;
+    let Some(installed) = db.packages.get(pkg_name) else {
+        bail!("Package {} not found", pkg_name);
+    };
+
+    let mut damaged = HashMap::new();
+    for file in &installed.files {
+        let problems = check_file(file)?;
+        if problems.is_empty() {
+            continue;
+        }
+        // An edited config file is the user's; only a missing one is put back.
+        if file.config && !problems.contains(&FileProblem::Missing) {
+            print_info(&format!("Leaving your edited {} alone", file.path));
+            continue;
+        }
+        damaged.insert(file.path.clone(), problems);
+    }
+
+    if damaged.is_empty() {
+        print_success(&format!("Package {} is properly installed, nothing to repair", pkg_name));
+        return Ok(());
+    }
+
+    let archive_path = archive_for_installed(pkg_name, installed)?;
+    let root = &settings()?.root;
+    let mut archive = open_archive(&archive_path)?;
+    let mut restored = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(path) = package_path(&entry.path()?) else { continue };
+        let Some(problems) = damaged.get(&path) else { continue };
+
+        let on_disk = root_path(&path)?;
+        if problems.contains(&FileProblem::Replaced) || problems.contains(&FileProblem::Modified) {
+            // Clear whatever is in the way; a directory is only removed if empty.
+            match fs::symlink_metadata(&on_disk) {
+                Ok(meta) if meta.is_dir() => {
+                    if !entry.header().entry_type().is_dir()
+                        && let Err(e) = fs::remove_dir(&on_disk)
+                    {
+                        print_error(&format!("{} is now a directory, not touching it ({})", path, e));
+                        continue;
+                    }
+                }
+                Ok(_) => fs::remove_file(&on_disk)?,
+                Err(_) => {}
+            }
+        }
+
+        entry.unpack_in(root)?;
+        println!("  {} {}", "restored".green(), path);
+        restored.push(path);
+    }
+
+    triggers::touch(restored.iter().map(String::as_str));
+
+    // Keep the DB in step with what is on disk now (e.g. entries migrated without hashes).
+    if let Some(installed) = db.packages.get_mut(pkg_name) {
+        for file in installed.files.iter_mut().filter(|f| restored.contains(&f.path)) {
+            let (shared, config) = (file.shared, file.config);
+            *file = FileEntry::capture(&file.path, &root_path(&file.path)?)?;
+            file.shared = shared;
+            file.config = config;
+        }
+    }
+    db::save(&db)?;
+
+    let missing_from_archive = damaged.len() - restored.len();
+    if missing_from_archive > 0 {
+        bail!(
+            "Repaired {} file(s) of {}, {} could not be restored",
+            restored.len(),
+            pkg_name,
+            missing_from_archive
+        );
+    }
+
+    print_success(&format!("Repaired {} file(s) of {}", restored.len(), pkg_name));
+    Ok(())
 }
 
-pub fn list() -> Result<()> {
-    print_info("Listing installed packages:");
+pub fn list(only_orphans: bool) -> Result<()> {
+    let mut db = db::load()?;
+    if only_orphans {
+        print_info("Listing orphaned packages:");
+        let orphans = orphans(&db);
+        db.packages.retain(|name, _| orphans.contains(name));
+        if db.packages.is_empty() {
+            println!("No orphaned packages.");
+            return Ok(());
+        }
+    } else {
+        print_info("Listing installed packages:");
+    }
 
-    let db = read_tracking_file()?;
-    if db.as_object().unwrap().is_empty() {
+    if db.packages.is_empty() {
         println!("No packages installed.");
         return Ok(());
     }
@@ -233,40 +1426,377 @@ pub fn list() -> Result<()> {
     println!("{:<20} {:<10} {:<10} {:<20}", "Package", "Version", "OS", "Description");
     println!("{:-<20} {:-<10} {:-<10} {:-<20}", "", "", "", "");
 
-    for (name, info) in db.as_object().unwrap() {
-        let meta = &info["meta"];
+    for (name, info) in &db.packages {
+        let meta = &info.meta;
+        let hold = match settings()?.holds.get(name) {
+            Some(constraint) => format!(" [held {}]", constraint).yellow().to_string(),
+            None => String::new(),
+        };
         println!(
-            "{:<20} {:<10} {:<10} {}",
+            "{:<20} {:<10} {:<10} {}{}",
             name.green().bold(),
-                 meta["version"].as_str().unwrap_or("unknown"),
-                 meta["os"].as_str().unwrap_or("unknown"),
-                 meta["description"].as_str().unwrap_or("No description")
+                 meta.version,
+                 meta.os,
+                 meta.description,
+                 hold
         );
     }
 
     Ok(())
 }
 
-pub fn _verify(pkg_name: &str) -> Result<()> {
-    let db = read_tracking_file()?;
-    if let Some(pkg) = db.get(pkg_name) {
-        let files = pkg["files"].as_array().unwrap();
-        let mut missing = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileProblem {
+    Missing,
+    /// Something of a different kind (file, directory, symlink) now sits at the path.
+    Replaced,
+    Modified,
+    PermissionsChanged,
+}
+
+impl FileProblem {
+    fn label(self) -> &'static str {
+        match self {
+            FileProblem::Missing => "missing",
+            FileProblem::Replaced => "replaced",
+            FileProblem::Modified => "modified",
+            FileProblem::PermissionsChanged => "permissions changed",
+        }
+    }
+}
+
+fn differs<T: PartialEq>(recorded: &Option<T>, now: &Option<T>) -> bool {
+    recorded.is_some() && recorded != now
+}
+
+/// Compare a tracked file with what is on disk. Attributes not recorded in the DB are not checked.
+pub fn check_file(entry: &FileEntry) -> Result<Vec<FileProblem>> {
+    let on_disk = root_path(&entry.path)?;
+    let Ok(metadata) = fs::symlink_metadata(&on_disk) else {
+        return Ok(vec![FileProblem::Missing]);
+    };
+
+    let current = FileEntry::capture(&entry.path, &on_disk)?;
+    if entry.kind.is_some() && entry.kind != current.kind {
+        return Ok(vec![FileProblem::Replaced]);
+    }
+
+    let mut problems = Vec::new();
+    if differs(&entry.sha256, &current.sha256)
+        || differs(&entry.size, &current.size)
+        || differs(&entry.target, &current.target)
+    {
+        problems.push(FileProblem::Modified);
+    }
+    if differs(&entry.mode, &current.mode) {
+        problems.push(FileProblem::PermissionsChanged);
+    }
+
+    // Entries migrated from old DBs carry no kind; at least notice a file turned into a directory.
+    if entry.kind.is_none() && entry.sha256.is_some() && metadata.is_dir() {
+        problems.push(FileProblem::Replaced);
+    }
+
+    Ok(problems)
+}
+
+/// Check installed files against installed.db. Fails if any package has problems,
+/// so scripts can rely on the exit code.
+pub fn verify(pkg_names: &[String]) -> Result<()> {
+    let db = db::load()?;
+    let names: Vec<&String> = if pkg_names.is_empty() {
+        db.packages.keys().collect()
+    } else {
+        pkg_names.iter().collect()
+    };
+
+    let mut failed = 0;
+    for name in names {
+        let Some(pkg) = db.packages.get(name) else {
+            print_error(&format!("Package {} not found", name));
+            failed += 1;
+            continue;
+        };
 
-        for file in files {
-            let path = file.as_str().unwrap();
-            if !Path::new(path).exists() {
-                missing.push(path);
+        let mut problems = Vec::new();
+        for file in &pkg.files {
+            for problem in check_file(file)? {
+                problems.push((problem, file.path.as_str()));
             }
         }
 
-        if missing.is_empty() {
-            print_success(&format!("Package {} is properly installed", pkg_name));
-        } else {
-            print_error(&format!("Missing files: {:?}", missing));
+        if problems.is_empty() {
+            print_success(&format!("Package {} is properly installed", name));
+            continue;
+        }
+
+        failed += 1;
+        print_error(&format!("Package {} has {} problem(s):", name, problems.len()));
+        for (problem, path) in problems {
+            println!("  {:<20} {}", problem.label().yellow(), path);
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} package(s) failed verification", failed);
+    }
+    Ok(())
+}
+
+/// Turn a user-supplied path into the package path it refers to: made absolute against
+/// the current directory and stripped of the configured root.
+fn to_package_path(path: &Path) -> Result<String> {
+    let mut absolute = PathBuf::new();
+    for component in std::path::absolute(path)
+        .with_context(|| format!("Invalid path: {}", path.display()))?
+        .components()
+    {
+        match component {
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            Component::CurDir => {}
+            other => absolute.push(other),
+        }
+    }
+    let root = &settings()?.root;
+    let relative = absolute.strip_prefix(root).unwrap_or(&absolute);
+    Ok(package_path(relative).unwrap_or_else(|| "/".to_string()))
+}
+
+pub fn owns(path: &Path) -> Result<()> {
+    let index = db::load_index()?;
+    let mut candidates = vec![to_package_path(path)?];
+
+    // Also look up what a symlink (or a path through one) resolves to.
+    if let Ok(resolved) = fs::canonicalize(root_path(&candidates[0])?) {
+        let resolved = to_package_path(&resolved)?;
+        if !candidates.contains(&resolved) {
+            candidates.push(resolved);
         }
+    }
+
+    let mut found = false;
+    for candidate in &candidates {
+        if let Some(owners) = index.get(candidate) {
+            found = true;
+            println!("{} is owned by {}", candidate, owners.join(", ").green().bold());
+        }
+    }
+
+    if !found {
+        bail!("No package owns {}", candidates.join(" or "));
+    }
+    Ok(())
+}
+
+pub fn files(pkg_name: &str) -> Result<()> {
+    let db = db::load()?;
+    let Some(pkg) = db.packages.get(pkg_name) else {
+        bail!("Package {} not found", pkg_name);
+    };
+
+    let mut paths: Vec<&str> = pkg.files.iter().map(|f| f.path.as_str()).collect();
+    paths.sort();
+    for path in paths {
+        println!("{}", path);
+    }
+    Ok(())
+}
+
+/// `YYYY-MM-DD HH:MM UTC` for a Unix timestamp.
+fn format_timestamp(secs: u64) -> String {
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60
+    )
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
     } else {
-        print_error(&format!("Package {} not found", pkg_name));
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn info_line(label: &str, value: &str) {
+    println!("{:<16} {}", format!("{}:", label).bold(), value);
+}
+
+/// Print an available value, flagging it when the installed copy differs.
+fn info_compare(label: &str, available: &str, installed: Option<&str>) {
+    match installed {
+        Some(installed) if installed != available => info_line(
+            label,
+            &format!("{} {}", available.yellow(), format!("(installed: {})", installed).dimmed()),
+        ),
+        _ => info_line(label, available),
+    }
+}
+
+pub fn info(pkg_name: &str) -> Result<()> {
+    let db = db::load()?;
+    let installed = db.packages.get(pkg_name);
+
+    let available = match repo::find_package(pkg_name) {
+        Ok(pkg) => Some(pkg),
+        Err(e) if installed.is_some() => {
+            print_info(&format!("Not available from the repositories: {}", e));
+            None
+        }
+        Err(e) => return Err(e),
+    };
+
+    let deps = |deps: &[String]| if deps.is_empty() { "None".to_string() } else { deps.join(", ") };
+
+    println!();
+    info_line("Name", &pkg_name.green().bold().to_string());
+
+    if let Some(pkg) = &available {
+        let meta = installed.map(|i| &i.meta);
+        info_compare("Version", &pkg.version, installed.map(|i| i.version.as_str()));
+        info_compare("Description", &pkg.description, meta.map(|m| m.description.as_str()));
+        info_compare("Author", &pkg.author, meta.map(|m| m.author.as_str()));
+        info_compare("License", &pkg.license, meta.map(|m| m.license.as_str()));
+        info_compare("Architecture", &pkg.arch, meta.map(|m| m.arch.as_str()));
+        info_compare("OS", &pkg.os, meta.map(|m| m.os.as_str()));
+        let installed_deps = meta.map(|m| deps(&m.deps));
+        info_compare("Depends on", &deps(&pkg.deps), installed_deps.as_deref());
+        info_line(
+            "Download size",
+            &pkg.size.map_or_else(|| "unknown".to_string(), format_size),
+        );
+        info_line("Repository", &pkg.repo);
+        info_line("URL", &package_url(pkg).unwrap_or_else(|_| pkg.url.clone()));
+    } else if let Some(installed) = installed {
+        let meta = &installed.meta;
+        info_line("Version", &installed.version);
+        info_line("Description", &meta.description);
+        info_line("Author", &meta.author);
+        info_line("License", &meta.license);
+        info_line("Architecture", &meta.arch);
+        info_line("OS", &meta.os);
+        info_line("Depends on", &deps(&meta.deps));
+    }
+
+    match installed {
+        Some(installed) => {
+            let reason = match installed.reason {
+                InstallReason::Explicit => "explicitly installed",
+                InstallReason::Dependency => "installed as a dependency",
+            };
+            let date = if installed.install_date == 0 {
+                "unknown".to_string()
+            } else {
+                format_timestamp(installed.install_date)
+            };
+            info_line("Installed", &format!("{} ({})", installed.version, reason));
+            info_line("Install date", &date);
+            let from = installed.source.as_deref().or(installed.repo.as_deref());
+            info_line("Installed from", from.unwrap_or("unknown"));
+            info_line("Files", &installed.files.len().to_string());
+        }
+        None => info_line("Installed", "no"),
     }
+    println!();
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_entry() -> PackageInfo {
+        serde_json::from_value(serde_json::json!({
+            "name": "foo",
+            "version": "1.2",
+            "description": "Foo",
+            "url": "foo-1.2.pkg",
+            "os": "linux",
+            "arch": "x86_64",
+            "deps": ["libbar", "libbaz"],
+            "author": "me",
+            "license": "MIT",
+        }))
+        .unwrap()
+    }
+
+    fn meta(extra: &str) -> Meta {
+        toml::from_str(&format!(
+            "name = \"foo\"\nversion = \"1.2\"\narch = \"x86_64\"\nos = \"linux\"\n\
+             author = \"someone else\"\nlicense = \"GPL\"\n{}",
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn treats_etc_as_config_by_default() {
+        assert!(is_config(&[], "/etc/foo.conf"));
+        assert!(is_config(&[], "/etc/foo/bar.conf"));
+        assert!(!is_config(&[], "/etc"));
+        assert!(!is_config(&[], "/etcetera/foo"));
+        assert!(!is_config(&[], "/usr/bin/foo"));
+    }
+
+    #[test]
+    fn matches_declared_config_paths() {
+        let config = ["/opt/foo/foo.toml".to_string(), "/opt/foo/conf.d/**".to_string()];
+        assert!(is_config(&config, "/opt/foo/foo.toml"));
+        assert!(is_config(&config, "/opt/foo/conf.d/a.toml"));
+        assert!(is_config(&config, "/opt/foo/conf.d/sub/b.toml"));
+        assert!(!is_config(&config, "/opt/foo/conf.d"));
+        assert!(!is_config(&config, "/opt/foo/conf.dx/a.toml"));
+        assert!(!is_config(&config, "/opt/foo/foo.toml.bak"));
+        // Declaring config files replaces the /etc default.
+        assert!(!is_config(&config, "/etc/foo.conf"));
+    }
+
+    #[test]
+    fn accepts_meta_matching_the_index() {
+        // Dependency order and descriptive fields may differ.
+        assert!(check_meta(&index_entry(), &meta("deps = [\"libbaz\", \"libbar\"]")).is_ok());
+    }
+
+    #[test]
+    fn refuses_meta_naming_another_package() {
+        let message = check_meta(&index_entry(), &meta("deps = [\"libbar\"]"))
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("deps: index says 'libbar, libbaz', archive says 'libbar'"));
+
+        let mut other = index_entry();
+        other.name = "evil".to_string();
+        other.arch = "aarch64".to_string();
+        let message = check_meta(&other, &meta("deps = [\"libbar\", \"libbaz\"]"))
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("name: index says 'evil', archive says 'foo'"));
+        assert!(message.contains("arch: index says 'aarch64', archive says 'x86_64'"));
+    }
+}
@@ -3,11 +3,17 @@ use anyhow::{Context, Result};
 use colored::Colorize;
 use serde::Deserialize;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs;
 use std::path::PathBuf;
-use std::process::Command;
-use crate::config::get_repos;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use crate::config::{
+    get_cache_dir, get_repos, load_repos, load_system_repos, load_user_repos, remove_system_repo,
+    save_system_repo, save_user_repos, settings, SignaturePolicy,
+};
+use crate::http;
+use crate::scripts::{ScriptPolicy, Scripts};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct PackageInfo {
@@ -20,13 +26,29 @@ pub struct PackageInfo {
     pub deps: Vec<String>,
     pub author: String,
     pub license: String,
-    
+    /// Archive size in bytes, if the index publishes it.
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// SHA-256 of the archive, if the index publishes it.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Config files the package declares (exact paths or `dir/**`); none means all of `/etc`.
+    #[serde(default)]
+    pub config: Vec<String>,
+    #[serde(default)]
+    pub scripts: Scripts,
+
     #[serde(skip)]
     pub base_url: String, 
+    /// Name of the configured repository this entry came from.
+    #[serde(skip)]
+    pub repo: String,
 }
 
+/// `meta.toml` at the root of a `.pkg` archive.
 #[derive(Debug, Deserialize)]
-pub struct _Meta {
+#[serde(deny_unknown_fields)]
+pub struct Meta {
     pub name: String,
     pub version: String,
     pub arch: String,
@@ -35,60 +57,122 @@ pub struct _Meta {
     pub author: String,
     pub license: String,
     pub description: Option<String>,
+    #[serde(default)]
+    pub config: Vec<String>,
+    #[serde(default)]
+    pub scripts: Scripts,
 }
 
-const REPOS_CONFIG: &str = "repos.list";
-
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct RepoConfig {
     pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub gpg_key: Option<String>,
-}
+    /// Whether packages from this repository may run install scripts.
+    #[serde(default, skip_serializing_if = "ScriptPolicy::is_default")]
+    pub scripts: ScriptPolicy,
 
-fn verify_repo_index(repo_url: &str) -> Result<()> {
-    let index = download_file(&format!("{}/index.json", repo_url))?;
-    let signature = download_file(&format!("{}/index.json.asc", repo_url))?;
+    /// File this entry was loaded from.
+    #[serde(skip)]
+    pub source: PathBuf,
+}
 
+/// Check `content` against an armored detached signature using the user's gpg keyring.
+fn gpg_verify(content: &[u8], signature: &[u8]) -> Result<bool> {
     let temp_dir = tempfile::tempdir()?;
-    let index_path = temp_dir.path().join("index.json");
-    let sig_path = temp_dir.path().join("index.sig");
+    let content_path = temp_dir.path().join("content");
+    let sig_path = temp_dir.path().join("content.sig");
 
-    std::fs::write(&index_path, index)?;
+    std::fs::write(&content_path, content)?;
     std::fs::write(&sig_path, signature)?;
 
-    let status = Command::new("gpg")
-    .args(&["--verify", sig_path.to_str().unwrap(), index_path.to_str().unwrap()])
-    .status()?;
+    let mut gpg = Command::new("gpg");
+    gpg.args(["--verify", sig_path.to_str().unwrap(), content_path.to_str().unwrap()]);
+    if crate::cli::quiet() {
+        gpg.stdout(Stdio::null()).stderr(Stdio::null());
+    }
+    let status = gpg.status()?;
+
+    Ok(status.success())
+}
+
+/// Download `url` together with its `.asc` signature and verify it.
+fn download_signed(url: &str) -> Result<Vec<u8>> {
+    let content = download_file(url)?;
+    let signature = download_file(&format!("{}.asc", url))?;
 
-    if !status.success() {
-        return Err(anyhow::anyhow!("❌ Repository signature verification failed!"));
+    if !gpg_verify(&content, &signature)? {
+        return Err(anyhow::anyhow!("❌ Signature verification failed for {}", url));
     }
+    Ok(content)
+}
 
-    Ok(())
+/// Download a repository file, checking its signature as `signature_policy` demands.
+fn download_with_policy(url: &str) -> Result<Vec<u8>> {
+    match settings()?.signature_policy {
+        SignaturePolicy::Required => download_signed(url),
+        SignaturePolicy::Warn => download_signed(url).or_else(|e| {
+            print_error(&format!("{} (continuing: signature_policy = \"warn\")", e));
+            download_file(url)
+        }),
+        SignaturePolicy::Skip => download_file(url),
+    }
+}
+
+/// Check a package file against its detached signature, if it has one. A file without one
+/// is only refused when `required`; `signature_policy` is for repositories and does not apply.
+pub fn verify_package_file(
+    name: &str,
+    content: &[u8],
+    signature: Option<&[u8]>,
+    required: bool,
+) -> Result<()> {
+    match signature {
+        Some(signature) if gpg_verify(content, signature)? => Ok(()),
+        Some(_) => Err(anyhow::anyhow!("❌ Signature verification failed for {}", name)),
+        None if required => Err(anyhow::anyhow!(
+            "No signature found for {} (expected {}.asc)",
+            name,
+            name
+        )),
+        None => {
+            print_info(&format!("{} is not signed; installing it unverified", name));
+            Ok(())
+        }
+    }
+}
+
+fn verify_repo_index(repo_url: &str) -> Result<()> {
+    download_signed(&format!("{}/index.json", repo_url))
+        .map(|_| ())
+        .context("❌ Repository signature verification failed!")
 }
 
 pub fn fetch_repository(repo_url: &str) -> Result<HashMap<String, PackageInfo>> {
     let url = format!("{}/index.json", repo_url.trim_end_matches('/'));
     print_info(&format!("Fetching repository: {}", url));
 
-    let response = reqwest::blocking::get(&url)
+    let body = http::get_bytes(&url)
     .with_context(|| format!("Failed to fetch repository: {}", url))?;
 
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!(
-            "Repository returned status: {}",
-            response.status()
-        ));
-    }
-
-    let index: Value = response
-    .json()
+    let index: Value = serde_json::from_slice(&body)
     .with_context(|| "Failed to parse repository index")?;
 
     let mut packages = HashMap::new();
     let clean_repo_url = repo_url.trim_end_matches('/');
     if let Some(pkgs) = index.get("packages").and_then(Value::as_object) {
         for (name, info) in pkgs {
+            // Defaulting would silently drop every hook, including `pre_*` ones meant to abort.
+            let scripts = match info.get("scripts") {
+                None | Some(Value::Null) => Scripts::default(),
+                Some(scripts) => match serde_json::from_value(scripts.clone()) {
+                    Ok(scripts) => scripts,
+                    Err(e) => {
+                        print_error(&format!("Skipping {} in {}: invalid scripts: {}", name, url, e));
+                        continue;
+                    }
+                },
+            };
             packages.insert(
                 name.clone(),
                 PackageInfo {
@@ -103,7 +187,14 @@ pub fn fetch_repository(repo_url: &str) -> Result<HashMap<String, PackageInfo>>
                         .unwrap_or_default(),
                     author: info["author"].as_str().unwrap_or("unknown").to_string(),
                     license: info["license"].as_str().unwrap_or("unknown").to_string(),
+                    size: info["size"].as_u64(),
+                    sha256: info["sha256"].as_str().map(String::from),
+                    config: info["config"].as_array()
+                        .map(|arr| arr.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                        .unwrap_or_default(),
+                    scripts,
                     base_url: clean_repo_url.to_string(),
+                    repo: String::new(),
                 },
             );
         }
@@ -115,18 +206,38 @@ pub fn fetch_repository(repo_url: &str) -> Result<HashMap<String, PackageInfo>>
 
 pub fn search(query: &str) -> Result<()> {
     let repos = crate::config::get_repos()?;
+    let repos: Vec<(&String, &RepoConfig)> = repos.iter().collect();
+    let parallelism = settings()?.parallelism.max(1);
     let mut results = Vec::new();
 
-    for (repo_name, repo_config) in repos.iter() {
-        match fetch_repository(&repo_config.url) {
-            Ok(packages) => {
-                for (_, pkg) in packages {
-                    if pkg.name.contains(query) || pkg.description.contains(query) {
-                        results.push(pkg.clone());
+    for chunk in repos.chunks(parallelism) {
+        let fetched: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = chunk
+                .iter()
+                .map(|(name, config)| (name, scope.spawn(|| fetch_repository(&config.url))))
+                .collect();
+            handles
+                .into_iter()
+                .map(|(name, handle)| {
+                    let result = handle
+                        .join()
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("fetch thread panicked")));
+                    (name, result)
+                })
+                .collect()
+        });
+
+        for (repo_name, result) in fetched {
+            match result {
+                Ok(packages) => {
+                    for (_, pkg) in packages {
+                        if pkg.name.contains(query) || pkg.description.contains(query) {
+                            results.push(pkg.clone());
+                        }
                     }
                 }
+                Err(e) => print_error(&format!("Error fetching repo {}: {}", repo_name, e)),
             }
-            Err(e) => print_error(&format!("Error fetching repo {}: {}", repo_name, e)),
         }
     }
     
@@ -154,97 +265,109 @@ pub fn search(query: &str) -> Result<()> {
 pub fn find_package(pkg_name: &str) -> Result<PackageInfo> {
     let repos = get_repos()?;
 
-    for (_name, repo_config) in repos.iter() {
+    for (name, repo_config) in repos.iter() {
         verify_repository(&repo_config.url)?;
         let packages = fetch_repository(&repo_config.url)?;
         if let Some(pkg) = packages.get(pkg_name) {
-            return Ok(pkg.clone());
+            let mut pkg = pkg.clone();
+            pkg.repo = name.clone();
+            return Ok(pkg);
         }
     }
 
     Err(anyhow::anyhow!("Package '{}' not found in any repository", pkg_name))
 }
 
-pub fn repo_add(url: &str, name: Option<&str>) -> Result<()> {
+/// Every package offered by the configured repositories, each repository fetched once.
+/// When several repositories offer a name, the first one in name order wins.
+pub fn fetch_all_packages() -> Result<HashMap<String, PackageInfo>> {
+    let mut repos: Vec<_> = get_repos()?.into_iter().collect();
+    repos.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut all = HashMap::new();
+    for (name, repo_config) in repos {
+        verify_repository(&repo_config.url)?;
+        for (pkg_name, mut pkg) in fetch_repository(&repo_config.url)? {
+            pkg.repo = name.clone();
+            all.entry(pkg_name).or_insert(pkg);
+        }
+    }
+    Ok(all)
+}
+
+pub fn repo_add(url: &str, name: Option<&str>, system: bool) -> Result<()> {
     let repo_name = name.unwrap_or_else(|| {
         url.split('/').nth(2).unwrap_or("unknown")
     });
 
-    let mut config = load_repos_config()?;
-    if config.contains_key(repo_name) {
-        return Err(anyhow::anyhow!("Repository '{}' already exists", repo_name));
+    if let Some(existing) = load_repos()?.get(repo_name) {
+        return Err(anyhow::anyhow!(
+            "Repository '{}' already exists (defined in {})",
+            repo_name,
+            existing.source.display()
+        ));
     }
 
     verify_repository(url)?;
 
-    config.insert(repo_name.to_string(), RepoConfig {
+    let repo = RepoConfig {
         url: url.to_string(),
         gpg_key: if url.ends_with('/') {
             format!("{}gpg-key.asc", url).into()
         } else {
             format!("{}/gpg-key.asc", url).into()
         },
-    });
-
-    save_repos_config(&config)?;
-    print_success(&format!("Added repository '{}'", repo_name));
+        scripts: ScriptPolicy::default(),
+        source: PathBuf::new(),
+    };
+
+    if system {
+        let path = save_system_repo(repo_name, &repo)?;
+        print_success(&format!("Added repository '{}' to {}", repo_name, path.display()));
+    } else {
+        let mut config = load_user_repos()?;
+        config.insert(repo_name.to_string(), repo);
+        save_user_repos(&config)?;
+        print_success(&format!("Added repository '{}'", repo_name));
+    }
     Ok(())
 }
 
 pub fn repo_remove(name: &str) -> Result<()> {
-    let mut config = load_repos_config()?;
-    if config.remove(name).is_none() {
+    let mut user = load_user_repos()?;
+    if user.remove(name).is_some() {
+        save_user_repos(&user)?;
+    } else if let Some(repo) = load_system_repos()?.get(name) {
+        remove_system_repo(name, &repo.source)?;
+    } else {
         return Err(anyhow::anyhow!("Repository '{}' not found", name));
     }
-    save_repos_config(&config)?;
     crate::cli::print_success(&format!("Removed repository '{}'", name));
     Ok(())
 }
 
 pub fn repo_list() -> Result<()> {
-    let config = load_repos_config()?;
+    let config = load_repos()?;
     if config.is_empty() {
         println!("No repositories configured.");
         return Ok(());
     }
 
-    println!("{:<20} {:<40}", "NAME", "URL");
-    println!("{:-<20} {:-<40}", "", "");
-    for (name, repo) in config {
-        println!("{:<20} {:<40}", name.blue().bold(), repo.url);
-    }
-    Ok(())
-}
-
-pub fn load_repos_config() -> Result<HashMap<String, RepoConfig>> {
-    let path = get_config_path(REPOS_CONFIG)?;
-    if !path.exists() {
-        return Ok(HashMap::new());
-    }
-    let content = fs::read_to_string(path)?;
-    Ok(serde_json::from_str(&content)?)
-}
+    let mut repos: Vec<_> = config.into_iter().collect();
+    repos.sort_by(|a, b| a.0.cmp(&b.0));
 
-fn save_repos_config(config: &HashMap<String, RepoConfig>) -> Result<()> {
-    let path = get_config_path(REPOS_CONFIG)?;
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+    println!("{:<20} {:<40} {:<20}", "NAME", "URL", "SOURCE");
+    println!("{:-<20} {:-<40} {:-<20}", "", "", "");
+    for (name, repo) in repos {
+        println!("{:<20} {:<40} {}", name.blue().bold(), repo.url, repo.source.display());
     }
-    fs::write(path, serde_json::to_string_pretty(config)?)?;
     Ok(())
 }
 
-fn get_config_path(filename: &str) -> Result<PathBuf> {
-    Ok(dirs::config_dir()
-    .ok_or_else(|| anyhow::anyhow!("Config directory not found"))?
-    .join("anspm")
-    .join(filename))
-}
-
 pub fn repo_update_keys() -> Result<()> {
     use std::process::Command;
 
-    let repos = load_repos_config()?;
+    let repos = load_repos()?;
 
     if repos.is_empty() {
         print_info("No repositories configured - nothing to update");
@@ -278,7 +401,7 @@ pub fn repo_update_keys() -> Result<()> {
 }
 
 pub fn repo_verify(name: &str) -> Result<()> {
-    let config = load_repos_config()?;
+    let config = load_repos()?;
     let repo = config.get(name)
     .ok_or_else(|| anyhow::anyhow!("Repository '{}' not found", name))?;
 
@@ -288,13 +411,139 @@ pub fn repo_verify(name: &str) -> Result<()> {
 }
 
 fn download_file(url: &str) -> Result<Vec<u8>> {
-    let response = reqwest::blocking::get(url)?;
-    if !response.status().is_success() {
-        return Err(anyhow::anyhow!("Failed to download file: {}", url));
-    }
-    Ok(response.bytes()?.to_vec())
+    http::get_bytes(url).with_context(|| format!("Failed to download file: {}", url))
 }
 
 fn verify_repository(url: &str) -> Result<()> {
-    verify_repo_index(url)
+    match settings()?.signature_policy {
+        SignaturePolicy::Required => verify_repo_index(url),
+        SignaturePolicy::Warn => {
+            if let Err(e) = verify_repo_index(url) {
+                print_error(&format!("{:#} (continuing: signature_policy = \"warn\")", e));
+            }
+            Ok(())
+        }
+        SignaturePolicy::Skip => Ok(()),
+    }
+}
+
+/// Package name -> paths it ships, as published in a repository's `files.json`.
+pub type FileList = HashMap<String, Vec<String>>;
+
+const FILE_LIST_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Fetch a repository's signed file list. With `max_age`, a cached copy younger than
+/// that is used instead of going to the network.
+fn fetch_file_list(repo_name: &str, repo: &RepoConfig, max_age: Option<Duration>) -> Result<FileList> {
+    let cache_path = get_cache_dir()?.join(format!("{}.files.json", repo_name));
+
+    let fresh = max_age.is_some_and(|max_age| {
+        fs::metadata(&cache_path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age < max_age)
+    });
+
+    let content = if fresh {
+        fs::read(&cache_path)?
+    } else {
+        let url = format!("{}/files.json", repo.url.trim_end_matches('/'));
+        let content = download_with_policy(&url)?;
+        crate::db::write_atomic(&cache_path, &content)?;
+        content
+    };
+
+    let index: Value = serde_json::from_slice(&content)
+        .with_context(|| format!("Failed to parse file list of {}", repo_name))?;
+    let mut files = FileList::new();
+    if let Some(pkgs) = index.get("packages").and_then(Value::as_object) {
+        for (name, paths) in pkgs {
+            let paths = paths
+                .as_array()
+                .map(|arr| arr.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+                .unwrap_or_default();
+            files.insert(name.clone(), paths);
+        }
+    }
+    Ok(files)
 }
+
+/// A path query (containing `/`) matches exactly; anything else matches a file name.
+fn path_matches(path: &str, query: &str) -> bool {
+    if query.contains('/') {
+        path == format!("/{}", query.trim_start_matches('/'))
+    } else {
+        path.rsplit('/').next() == Some(query)
+    }
+}
+
+/// (repo, package, path) for every published file matching `query`.
+fn find_providers(
+    query: &str,
+    max_age: Option<Duration>,
+    filter: impl Fn(&str) -> bool,
+) -> Result<Vec<(String, String, String)>> {
+    let mut repos: Vec<_> = get_repos()?.into_iter().collect();
+    repos.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut matches = Vec::new();
+    for (repo_name, repo) in repos {
+        let files = match fetch_file_list(&repo_name, &repo, max_age) {
+            Ok(files) => files,
+            Err(e) => {
+                print_error(&format!("Error fetching file list of {}: {}", repo_name, e));
+                continue;
+            }
+        };
+        for (pkg, paths) in files {
+            for path in paths {
+                if filter(&path) && path_matches(&path, query) {
+                    matches.push((repo_name.clone(), pkg.clone(), path));
+                }
+            }
+        }
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+pub fn provides(query: &str) -> Result<()> {
+    let matches = find_providers(query, None, |_| true)?;
+    if matches.is_empty() {
+        println!("No package provides '{}'", query);
+        return Ok(());
+    }
+
+    let installed = crate::db::load()?;
+    for (repo_name, pkg, path) in matches {
+        let marker = if installed.packages.contains_key(&pkg) { " [installed]" } else { "" };
+        println!("{}/{}{}: {}", repo_name, pkg.green().bold(), marker, path);
+    }
+    Ok(())
+}
+
+/// Helper for shell `command_not_found` hooks: suggests packages shipping `command`
+/// in a bin directory and exits with 127 like the shell would.
+pub fn command_not_found(command: &str) -> Result<()> {
+    let in_bin_dir = |path: &str| {
+        path.rsplit('/').nth(1).is_some_and(|dir| dir == "bin" || dir == "sbin")
+    };
+    // Runs from the shell on every unknown command: only the suggestion may be printed.
+    crate::cli::set_quiet();
+    let matches = find_providers(command, Some(FILE_LIST_MAX_AGE), in_bin_dir)
+        .unwrap_or_default();
+
+    // The same package may come from several repositories.
+    let pkgs: BTreeSet<&str> = matches.iter().map(|(_, pkg, _)| pkg.as_str()).collect();
+
+    if pkgs.is_empty() {
+        eprintln!("{}: command not found", command);
+    } else {
+        eprintln!("Command '{}' not found, but can be installed with:", command);
+        for pkg in pkgs {
+            eprintln!("  anspm install {}", pkg);
+        }
+    }
+    std::process::exit(127);
+}
\ No newline at end of file
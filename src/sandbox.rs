@@ -0,0 +1,228 @@
+use crate::config::root_path;
+use crate::db::FileEntry;
+use anyhow::Result;
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::libc;
+use nix::mount::{mount, MsFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::stat::Mode;
+use nix::unistd::{close, getegid, geteuid, write};
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// `CAP_SYS_ADMIN` from linux/capability.h, which the libc crate does not export.
+const CAP_SYS_ADMIN: libc::c_ulong = 21;
+
+/// Everything the sandboxed child needs, prepared before the fork: between fork and exec
+/// it must not allocate, as another thread may have held the allocator's lock.
+struct Sandbox {
+    uid_map: String,
+    gid_map: String,
+    writable: Vec<CString>,
+    /// Mount points to make read-only, with the flags each remount has to keep.
+    read_only: Vec<(CString, MsFlags)>,
+}
+
+/// Make `command` run without network access and with the filesystem read-only,
+/// apart from what the package owns (`files`).
+pub fn apply(command: &mut Command, files: &[FileEntry]) -> Result<()> {
+    let sandbox = prepare(files)?;
+    // SAFETY: `enter` only makes system calls on the data prepared above.
+    unsafe {
+        command.pre_exec(move || sandbox.enter());
+    }
+    Ok(())
+}
+
+fn prepare(files: &[FileEntry]) -> Result<Sandbox> {
+    let writable = writable_paths(files)?;
+    // Not just `/`: /var, /home, /tmp and the like are often filesystems of their own.
+    let read_only = mounts()?
+        .into_iter()
+        .filter(|(target, _)| !writable.contains(target))
+        .map(|(target, options)| Ok((c_path(&target)?, locked_flags(&options))))
+        .collect::<Result<_>>()?;
+    Ok(Sandbox {
+        // Even as root, a process can only map its own ids into a namespace it creates.
+        uid_map: format!("{0} {0} 1", geteuid()),
+        gid_map: format!("{0} {0} 1", getegid()),
+        writable: writable.iter().map(|path| c_path(path)).collect::<Result<_>>()?,
+        read_only,
+    })
+}
+
+fn c_path(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// On-disk paths the sandbox keeps writable: what the package owns outright, skipping
+/// anything already covered by an owned directory. Symlinks are left out, as binding one
+/// would expose its target.
+fn writable_paths(files: &[FileEntry]) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<&FileEntry> = files.iter().filter(|f| !f.shared).collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut writable: Vec<PathBuf> = Vec::new();
+    for file in files {
+        let path = root_path(&file.path)?;
+        if writable.iter().any(|dir| path.starts_with(dir)) {
+            continue;
+        }
+        if fs::symlink_metadata(&path).is_ok_and(|meta| !meta.file_type().is_symlink()) {
+            writable.push(path);
+        }
+    }
+    Ok(writable)
+}
+
+impl Sandbox {
+    /// Runs in the forked child: detach from the host's network and mounts, keep the
+    /// package's paths writable and make every mounted filesystem read-only.
+    fn enter(&self) -> io::Result<()> {
+        unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWNET)?;
+        write_proc(c"/proc/self/setgroups", b"deny")?;
+        write_proc(c"/proc/self/uid_map", self.uid_map.as_bytes())?;
+        write_proc(c"/proc/self/gid_map", self.gid_map.as_bytes())?;
+
+        let none = None::<&str>;
+        mount(none, "/", none, MsFlags::MS_REC | MsFlags::MS_PRIVATE, none)?;
+        for path in &self.writable {
+            mount(Some(path.as_c_str()), path.as_c_str(), none, MsFlags::MS_BIND, none)?;
+        }
+        for (target, locked) in &self.read_only {
+            let flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | *locked;
+            match mount(none, target.as_c_str(), none, flags, none) {
+                // What we cannot reach, the script cannot write to either.
+                Err(Errno::ENOENT | Errno::EACCES) => {}
+                result => result?,
+            }
+        }
+
+        // Root keeps its capabilities in this namespace and could remount everything
+        // read-write again. Taking CAP_SYS_ADMIN out of the bounding set makes exec drop it;
+        // entering the namespace already emptied the inheritable and ambient sets.
+        // SAFETY: prctl with plain integer arguments.
+        Errno::result(unsafe { libc::prctl(libc::PR_CAPBSET_DROP, CAP_SYS_ADMIN, 0, 0, 0) })?;
+        Errno::result(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+        Ok(())
+    }
+}
+
+fn write_proc(path: &CStr, content: &[u8]) -> io::Result<()> {
+    let fd = open(path, OFlag::O_WRONLY | OFlag::O_CLOEXEC, Mode::empty())?;
+    let written = write(fd, content);
+    close(fd)?;
+    written?;
+    Ok(())
+}
+
+/// Mount point and mount options of every mount in our namespace.
+fn mounts() -> io::Result<Vec<(PathBuf, String)>> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ').skip(4);
+            let target = fields.next()?;
+            let options = fields.next()?;
+            Some((PathBuf::from(unescape(target)), options.to_string()))
+        })
+        .collect())
+}
+
+/// Undo the octal escapes mountinfo uses for spaces, tabs, newlines and backslashes.
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let code = rest.get(i + 1..i + 4).and_then(|code| u8::from_str_radix(code, 8).ok());
+        match code {
+            Some(byte) => {
+                out.push(char::from(byte));
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Flags a remount has to keep: without them the kernel refuses the remount inside a user
+/// namespace, as they were set by a more privileged one.
+fn locked_flags(options: &str) -> MsFlags {
+    options
+        .split(',')
+        .map(|option| match option {
+            "nosuid" => MsFlags::MS_NOSUID,
+            "nodev" => MsFlags::MS_NODEV,
+            "noexec" => MsFlags::MS_NOEXEC,
+            "noatime" => MsFlags::MS_NOATIME,
+            "nodiratime" => MsFlags::MS_NODIRATIME,
+            "relatime" => MsFlags::MS_RELATIME,
+            "strictatime" => MsFlags::MS_STRICTATIME,
+            _ => MsFlags::empty(),
+        })
+        .fold(MsFlags::empty(), |flags, flag| flags | flag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unescapes_mountinfo_fields() {
+        assert_eq!(unescape("/mnt/usb"), "/mnt/usb");
+        assert_eq!(unescape("/mnt/my\\040disk"), "/mnt/my disk");
+        assert_eq!(unescape("/a\\011b\\012c\\134d"), "/a\tb\nc\\d");
+    }
+
+    #[test]
+    fn keeps_backslashes_that_are_not_escapes() {
+        assert_eq!(unescape("/odd\\x"), "/odd\\x");
+        assert_eq!(unescape("/short\\04"), "/short\\04");
+        assert_eq!(unescape("/end\\"), "/end\\");
+    }
+
+    #[test]
+    fn keeps_flags_locked_by_the_parent_namespace() {
+        assert_eq!(
+            locked_flags("ro,nosuid,nodev,relatime"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RELATIME
+        );
+        assert_eq!(locked_flags("rw"), MsFlags::empty());
+    }
+
+    #[test]
+    fn scripts_cannot_remount_read_write() {
+        let mut userns = Command::new("true");
+        unsafe {
+            userns.pre_exec(|| Ok(unshare(CloneFlags::CLONE_NEWUSER)?));
+        }
+        if let Err(e) = userns.status() {
+            eprintln!("skipping: no user namespaces here ({})", e);
+            return;
+        }
+
+        // What a hostile script running as root would try first.
+        let mut command = Command::new("sh");
+        command.args(["-c", "command -v mount >/dev/null || exit 77; mount -o remount,bind,rw /"]);
+        apply(&mut command, &[]).unwrap();
+        let status = command.stderr(std::process::Stdio::null()).status().unwrap();
+        if status.code() == Some(77) {
+            eprintln!("skipping: no mount command");
+            return;
+        }
+        assert!(!status.success(), "remounted / read-write inside the sandbox");
+    }
+}
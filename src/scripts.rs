@@ -0,0 +1,201 @@
+use crate::cli::{print_error, print_info};
+use crate::config::{load_repos, settings};
+use crate::db::FileEntry;
+#[cfg(target_os = "linux")]
+use crate::sandbox;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+const SANDBOX_UNSUPPORTED: &str = "the script sandbox needs Linux namespaces; \
+    set the repository's `scripts` policy to \"allow\" or \"refuse\", or use --no-scripts";
+
+/// How packages from a repository may run their scripts (`scripts` in the repository entry).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptPolicy {
+    #[default]
+    Allow,
+    /// No network, and the filesystem read-only apart from the package's own files.
+    Sandbox,
+    /// Packages with scripts can only be installed with `--no-scripts`.
+    Refuse,
+}
+
+impl ScriptPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == ScriptPolicy::default()
+    }
+}
+
+/// Shell snippets a package runs around its install, upgrade and removal.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scripts {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_install: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_install: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_upgrade: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_upgrade: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_remove: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_remove: Option<String>,
+}
+
+impl Scripts {
+    pub fn is_empty(&self) -> bool {
+        *self == Scripts::default()
+    }
+
+    pub fn get(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::PreInstall => &self.pre_install,
+            Hook::PostInstall => &self.post_install,
+            Hook::PreUpgrade => &self.pre_upgrade,
+            Hook::PostUpgrade => &self.post_upgrade,
+            Hook::PreRemove => &self.pre_remove,
+            Hook::PostRemove => &self.post_remove,
+        }
+        .as_deref()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PreInstall,
+    PostInstall,
+    PreUpgrade,
+    PostUpgrade,
+    PreRemove,
+    PostRemove,
+}
+
+impl Hook {
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::PreInstall => "pre_install",
+            Hook::PostInstall => "post_install",
+            Hook::PreUpgrade => "pre_upgrade",
+            Hook::PostUpgrade => "post_upgrade",
+            Hook::PreRemove => "pre_remove",
+            Hook::PostRemove => "post_remove",
+        }
+    }
+
+    fn is_pre(self) -> bool {
+        matches!(self, Hook::PreInstall | Hook::PreUpgrade | Hook::PreRemove)
+    }
+}
+
+/// Skip package scripts for the rest of this run (`install --no-scripts`).
+pub fn disable() {
+    DISABLED.store(true, Ordering::SeqCst);
+}
+
+/// Script policy of the repository a package came from; the default for packages
+/// whose repository is unknown or no longer configured.
+pub fn policy(repo: Option<&str>) -> Result<ScriptPolicy> {
+    let Some(repo) = repo else {
+        return Ok(ScriptPolicy::default());
+    };
+    Ok(load_repos()?.get(repo).map(|r| r.scripts).unwrap_or_default())
+}
+
+/// A package whose scripts are about to run.
+pub struct Package<'a> {
+    pub name: &'a str,
+    pub version: &'a str,
+    /// The version being replaced, on upgrade.
+    pub old_version: Option<&'a str>,
+    pub scripts: &'a Scripts,
+    pub policy: ScriptPolicy,
+    /// Installed files of the package; the sandbox leaves the ones it owns writable.
+    pub files: &'a [FileEntry],
+}
+
+/// Refuse to start installing a package whose repository does not allow scripts,
+/// unless scripts are disabled anyway.
+pub fn check_allowed(pkg: &Package) -> Result<()> {
+    if pkg.scripts.is_empty() || DISABLED.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    match pkg.policy {
+        ScriptPolicy::Refuse => bail!(
+            "{} has install scripts, which its repository does not allow; use --no-scripts to install it without them",
+            pkg.name
+        ),
+        ScriptPolicy::Sandbox if !cfg!(target_os = "linux") => {
+            bail!("{} has install scripts: {}", pkg.name, SANDBOX_UNSUPPORTED)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Run `hook` of `pkg` if the package has one, with `sh -c` in the configured root.
+/// The script gets the package version as `$1` (and the version being replaced as `$2`
+/// on upgrade), plus `ANSPM_PACKAGE`, `ANSPM_VERSION`, `ANSPM_OLD_VERSION` and `ANSPM_ROOT`.
+/// A failing pre-hook aborts the transaction; a failing post-hook is only reported,
+/// since the files are already in place by then.
+pub fn run(pkg: &Package, hook: Hook) -> Result<()> {
+    let Some(script) = pkg.scripts.get(hook) else {
+        return Ok(());
+    };
+    if DISABLED.load(Ordering::SeqCst) || pkg.policy == ScriptPolicy::Refuse {
+        print_info(&format!("Skipping {} script of {}", hook.name(), pkg.name));
+        return Ok(());
+    }
+    print_info(&format!("Running {} script of {}", hook.name(), pkg.name));
+
+    let root = &settings()?.root;
+    let mut command = Command::new("/bin/sh");
+    command
+        .arg("-c")
+        .arg(script)
+        .arg(format!("{}.{}", pkg.name, hook.name()))
+        .arg(pkg.version)
+        .args(pkg.old_version)
+        .env("ANSPM_PACKAGE", pkg.name)
+        .env("ANSPM_VERSION", pkg.version)
+        .env("ANSPM_ROOT", root)
+        .current_dir(root);
+    if let Some(old_version) = pkg.old_version {
+        command.env("ANSPM_OLD_VERSION", old_version);
+    }
+
+    if pkg.policy == ScriptPolicy::Sandbox {
+        sandbox::apply(&mut command, pkg.files)?;
+    }
+
+    let status = command
+        .status()
+        .with_context(|| format!("Failed to run {} script of {}", hook.name(), pkg.name))?;
+    if status.success() {
+        return Ok(());
+    }
+
+    let failure = format!("{} script of {} failed ({})", hook.name(), pkg.name, status);
+    if hook.is_pre() {
+        bail!("{}; aborting", failure);
+    }
+    print_error(&failure);
+    Ok(())
+}
+
+/// Elsewhere there is nothing to confine scripts with, so they do not run at all.
+#[cfg(not(target_os = "linux"))]
+mod sandbox {
+    use crate::db::FileEntry;
+    use anyhow::{bail, Result};
+    use std::process::Command;
+
+    pub fn apply(_command: &mut Command, _files: &[FileEntry]) -> Result<()> {
+        bail!("Cannot run package scripts: {}", super::SANDBOX_UNSUPPORTED)
+    }
+}
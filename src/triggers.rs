@@ -0,0 +1,116 @@
+use crate::cli::{print_error, print_info};
+use crate::config::{settings, system_triggers_dir};
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// Package paths installed or removed by the running command.
+static TOUCHED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// A system-wide action, defined in `/etc/anspm/triggers.d/<name>.toml`, run once after
+/// a command that installed or removed paths matching it.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Trigger {
+    /// Shown while the trigger runs.
+    description: Option<String>,
+    /// Glob patterns of package paths, e.g. `/usr/lib/*.so*` or `/usr/share/applications/**`.
+    paths: Vec<String>,
+    /// Shell command to run; it gets the matching paths on stdin, one per line.
+    run: String,
+}
+
+/// Note paths that were installed or removed, for `run_pending`.
+pub fn touch<'a>(paths: impl IntoIterator<Item = &'a str>) {
+    let mut touched = TOUCHED.lock().unwrap_or_else(|e| e.into_inner());
+    touched.extend(paths.into_iter().map(str::to_string));
+}
+
+fn load_triggers() -> Result<Vec<(String, Trigger)>> {
+    let dir = system_triggers_dir();
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    files.sort();
+
+    let mut triggers = Vec::new();
+    for path in files {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let trigger: Trigger = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        triggers.push((name, trigger));
+    }
+    Ok(triggers)
+}
+
+/// Run each trigger matching a path touched so far, once, and forget the touched paths.
+/// A failing trigger is reported but does not fail the command.
+pub fn run_pending() -> Result<()> {
+    let touched = std::mem::take(&mut *TOUCHED.lock().unwrap_or_else(|e| e.into_inner()));
+    if touched.is_empty() {
+        return Ok(());
+    }
+
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let root = &settings()?.root;
+
+    for (name, trigger) in load_triggers()? {
+        let patterns = trigger
+            .paths
+            .iter()
+            .map(|p| Pattern::new(p).with_context(|| format!("Invalid path '{}' in trigger {}", p, name)))
+            .collect::<Result<Vec<_>>>()?;
+        let matched: Vec<&String> = touched
+            .iter()
+            .filter(|path| patterns.iter().any(|p| p.matches_with(path, options)))
+            .collect();
+        if matched.is_empty() {
+            continue;
+        }
+
+        print_info(&format!(
+            "Running trigger {}: {}",
+            name,
+            trigger.description.as_deref().unwrap_or(&trigger.run)
+        ));
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&trigger.run)
+            .env("ANSPM_ROOT", root)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run trigger {}", name))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A trigger that does not read its input closes the pipe early; that is fine.
+            for path in &matched {
+                if writeln!(stdin, "{}", path).is_err() {
+                    break;
+                }
+            }
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            print_error(&format!("Trigger {} failed ({})", name, status));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+
+/// Compare two version strings segment by segment (`1.10` > `1.9`, `1.0-rc1` < `1.0` < `1.0.1`).
+/// Numeric segments compare as numbers. A segment with letters marks a pre-release: it sorts
+/// below any numeric segment and below the version ending there, and against another such
+/// segment as text.
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let split = |v: &str| -> Vec<String> {
+        v.split(['.', '-', '+', '_'])
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let (a, b) = (split(a), split(b));
+
+    // Pre-release segment < end of version < numeric segment.
+    fn rank(segment: Option<&String>) -> (u8, u64, &str) {
+        match segment {
+            Some(s) => match s.parse() {
+                Ok(n) => (2, n, ""),
+                Err(_) => (0, 0, s),
+            },
+            None => (1, 0, ""),
+        }
+    }
+    for i in 0..a.len().max(b.len()) {
+        let ord = rank(a.get(i)).cmp(&rank(b.get(i)));
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A version requirement such as `=1.2`, `>=1.0,<2.0` or `1.4.*`.
+#[derive(Debug, Clone)]
+pub struct Constraint {
+    parts: Vec<(Op, String)>,
+    raw: String,
+}
+
+impl Constraint {
+    pub fn parse(raw: &str) -> Result<Constraint> {
+        let mut parts = Vec::new();
+        for part in raw.split(',').map(str::trim) {
+            let (op, version) = if let Some(v) = part.strip_prefix(">=") {
+                (Op::Ge, v)
+            } else if let Some(v) = part.strip_prefix("<=") {
+                (Op::Le, v)
+            } else if let Some(v) = part.strip_prefix("==") {
+                (Op::Eq, v)
+            } else if let Some(v) = part.strip_prefix('>') {
+                (Op::Gt, v)
+            } else if let Some(v) = part.strip_prefix('<') {
+                (Op::Lt, v)
+            } else if let Some(v) = part.strip_prefix('=') {
+                (Op::Eq, v)
+            } else {
+                (Op::Eq, part)
+            };
+
+            let version = version.trim();
+            if version.is_empty() {
+                bail!("Invalid version constraint '{}'", raw);
+            }
+            parts.push((op, version.to_string()));
+        }
+        Ok(Constraint { parts, raw: raw.to_string() })
+    }
+
+    pub fn matches(&self, version: &str) -> bool {
+        self.parts.iter().all(|(op, wanted)| {
+            if *op == Op::Eq
+                && let Some(prefix) = wanted.strip_suffix(".*")
+            {
+                return version == prefix || version.starts_with(&format!("{}.", prefix));
+            }
+            let ord = compare(version, wanted.trim_end_matches(".*"));
+            match op {
+                Op::Eq => ord == Ordering::Equal,
+                Op::Lt => ord == Ordering::Less,
+                Op::Le => ord != Ordering::Greater,
+                Op::Gt => ord == Ordering::Greater,
+                Op::Ge => ord != Ordering::Less,
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_numeric_segments_as_numbers() {
+        assert_eq!(compare("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare("1.0.1", "1.0"), Ordering::Greater);
+        assert_eq!(compare("2", "10"), Ordering::Less);
+    }
+
+    #[test]
+    fn ranks_pre_releases_below_the_release() {
+        assert_eq!(compare("1.0-rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0-rc1", "1.0.1"), Ordering::Less);
+        assert_eq!(compare("1.0-rc1", "1.0-rc2"), Ordering::Less);
+        assert_eq!(compare("1.0-alpha", "1.0-beta"), Ordering::Less);
+        assert_eq!(compare("1.0", "1.0-rc1"), Ordering::Greater);
+        assert_eq!(compare("1.1-rc1", "1.0"), Ordering::Greater);
+    }
+
+    #[test]
+    fn matches_comparison_constraints() {
+        let range = Constraint::parse(">=1.0, <2.0").unwrap();
+        assert!(range.matches("1.0"));
+        assert!(range.matches("1.9.9"));
+        assert!(!range.matches("2.0"));
+        assert!(!range.matches("1.0-rc1"));
+        assert!(range.matches("2.0-rc1"));
+
+        assert!(Constraint::parse("=1.2").unwrap().matches("1.2"));
+        assert!(!Constraint::parse("1.2").unwrap().matches("1.2.1"));
+        assert!(Constraint::parse(">1.2").unwrap().matches("1.10"));
+        assert!(Constraint::parse("<=1.2").unwrap().matches("1.2"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let series = Constraint::parse("1.4.*").unwrap();
+        assert!(series.matches("1.4"));
+        assert!(series.matches("1.4.7"));
+        assert!(!series.matches("1.40"));
+        assert!(!series.matches("1.5.0"));
+    }
+
+    #[test]
+    fn rejects_empty_constraints() {
+        assert!(Constraint::parse(">=").is_err());
+        assert!(Constraint::parse("1.0,").is_err());
+    }
+}